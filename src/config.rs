@@ -0,0 +1,88 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// Environment variables that override the default locations.
+pub const INIT_VAR: &str = "MAL_INIT";
+pub const HISTORY_VAR: &str = "MAL_HISTORY";
+
+// Looks up an environment variable; tests substitute their own.
+pub type Vars<'a> = &'a dyn Fn(&str) -> Option<OsString>;
+
+pub fn os_vars(name: &str) -> Option<OsString> {
+    std::env::var_os(name)
+}
+
+fn var(vars: Vars, name: &str) -> Option<PathBuf> {
+    vars(name).filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+// An XDG base directory. Per the spec, unset or relative values fall
+// back to the default under $HOME.
+fn xdg_dir(vars: Vars, name: &str, default: &str) -> Option<PathBuf> {
+    match var(vars, name) {
+        Some(dir) if dir.is_absolute() => Some(dir),
+        _ => var(vars, "HOME").map(|home| home.join(default)),
+    }
+}
+
+// The file to load before the REPL starts: the --init flag, then
+// $MAL_INIT, then the first of $XDG_CONFIG_HOME/mal/init.mal and
+// ~/.malrc that exists.
+pub fn init_file(flag: Option<&str>, vars: Vars) -> Option<PathBuf> {
+    if let Some(f) = flag.map(PathBuf::from).or_else(|| var(vars, INIT_VAR)) {
+        return Some(f);
+    }
+    let xdg = xdg_dir(vars, "XDG_CONFIG_HOME", ".config").map(|d| d.join("mal/init.mal"));
+    let rc = var(vars, "HOME").map(|home| home.join(".malrc"));
+    xdg.into_iter().chain(rc).find(|f| f.is_file())
+}
+
+// Where REPL history is kept: the --history flag, then $MAL_HISTORY,
+// then $XDG_STATE_HOME/mal/history.
+pub fn history_file(flag: Option<&str>, vars: Vars) -> Option<PathBuf> {
+    flag.map(PathBuf::from)
+        .or_else(|| var(vars, HISTORY_VAR))
+        .or_else(|| xdg_dir(vars, "XDG_STATE_HOME", ".local/state").map(|d| d.join("mal/history")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn vars_of(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<OsString> {
+        let pairs: Vec<(String, String)> =
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| pairs.iter().find(|(k, _)| k == name).map(|(_, v)| OsString::from(v))
+    }
+
+    #[test]
+    fn test_history_file() {
+        let vars = vars_of(&[("HOME", "/home/u")]);
+        assert_eq!(history_file(None, &vars), Some(PathBuf::from("/home/u/.local/state/mal/history")));
+        assert_eq!(history_file(Some("h"), &vars), Some(PathBuf::from("h")));
+
+        let vars = vars_of(&[("HOME", "/home/u"), ("XDG_STATE_HOME", "/state"), (HISTORY_VAR, "")]);
+        assert_eq!(history_file(None, &vars), Some(PathBuf::from("/state/mal/history")));
+
+        let vars = vars_of(&[("HOME", "/home/u"), ("XDG_STATE_HOME", "rel"), (HISTORY_VAR, "/tmp/h")]);
+        assert_eq!(history_file(None, &vars), Some(PathBuf::from("/tmp/h")));
+        assert_eq!(history_file(None, &vars_of(&[])), None);
+    }
+
+    #[test]
+    fn test_init_file() {
+        let home = std::env::temp_dir().join(format!("mal-config-test-{}", std::process::id()));
+        fs::create_dir_all(home.join(".config/mal")).unwrap();
+        let vars = vars_of(&[("HOME", home.to_str().unwrap())]);
+
+        assert_eq!(init_file(None, &vars), None);
+        fs::write(home.join(".malrc"), "").unwrap();
+        assert_eq!(init_file(None, &vars), Some(home.join(".malrc")));
+        fs::write(home.join(".config/mal/init.mal"), "").unwrap();
+        assert_eq!(init_file(None, &vars), Some(home.join(".config/mal/init.mal")));
+        assert_eq!(init_file(Some("x.mal"), &vars), Some(PathBuf::from("x.mal")));
+
+        fs::remove_dir_all(&home).unwrap();
+    }
+}
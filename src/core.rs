@@ -1,5 +1,6 @@
+use regex::{Captures, Regex};
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::rc::Rc;
 use std::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -8,19 +9,37 @@ extern crate rustyline;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use crate::printer::pr_seq;
-use crate::reader::read_str;
-use crate::types::MalErr::ErrMalVal;
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, Float, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{MalArgs, MalRet, MalVal, _assoc, _dissoc, atom, error, func, hash_map};
+use crate::math;
+use crate::number::Number;
+use crate::random;
+use crate::pprint;
+use crate::printer::{self, pr_seq};
+use crate::reader::{read_all, read_str, read_str_from};
+use crate::types::MalErr::{ErrMalVal, ErrString};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Char, Float, Func, Hash, Int, List, MalFunc, Nil, Ratio, Set, Str, Sym,
+    Vector,
+};
+use crate::types::{
+    MalArgs, MalErr, MalRet, MalVal, _assoc, _dissoc, atom, error, func, hash_map, hash_set,
+};
 
 
-macro_rules! fn_t_num_num {
-    ($ret:ident, $fn:expr) => {{
-        |a: MalArgs| match (&a[0], &a[1]) {
-            (Int(a0), Int(a1)) => Ok($ret($fn(a0, a1))),
-            _ => error("expecting (int,int) args"),
-        }
+macro_rules! fn_num_fold {
+    ($unit:expr, $fn:expr) => {{
+        |a: MalArgs| num_fold(a, Number::Int($unit), $fn)
+    }};
+}
+
+macro_rules! fn_num_cmp {
+    ($fn:expr) => {{
+        |a: MalArgs| num_cmp(a, $fn)
+    }};
+}
+
+macro_rules! fn_num {
+    ($fn:expr) => {{
+        |a: MalArgs| Ok(MalVal::from($fn(&Number::try_from(&a[0])?)?))
     }};
 }
 
@@ -45,6 +64,49 @@ macro_rules! fn_str {
     }};
 }
 
+fn num_args(a: MalArgs) -> Result<Vec<Number>, MalErr> {
+    a.iter().map(Number::try_from).collect()
+}
+
+// (op) is the unit, (op x) is (op unit x) and (op x y ...) folds left.
+fn num_fold(a: MalArgs, unit: Number, f: fn(Number, Number) -> Result<Number, MalErr>) -> MalRet {
+    let mut ns = num_args(a)?.into_iter();
+    let res = match (ns.next(), ns.len()) {
+        (None, _) => unit,
+        (Some(x), 0) => f(unit, x)?,
+        (Some(x), _) => ns.try_fold(x, f)?,
+    };
+    Ok(MalVal::from(res))
+}
+
+// True when every adjacent pair of args satisfies f.
+fn num_cmp(a: MalArgs, f: fn(&Number, &Number) -> bool) -> MalRet {
+    let ns = num_args(a)?;
+    Ok(Bool(ns.windows(2).all(|w| f(&w[0], &w[1]))))
+}
+
+fn char(a: MalArgs) -> MalRet {
+    match a[0] {
+        Char(c) => Ok(Char(c)),
+        Int(n) => match u32::try_from(n).ok().and_then(char::from_u32) {
+            Some(c) => Ok(Char(c)),
+            None => error(&format!("char: invalid code point {}", n)),
+        },
+        _ => error("char: expecting int or char"),
+    }
+}
+
+// Characters become their code point; numbers are truncated towards zero.
+fn int(a: MalArgs) -> MalRet {
+    match a[0] {
+        Char(c) => Ok(Int(c as i64)),
+        ref n => match Number::try_from(n)? {
+            Number::Float(f) if !f.is_finite() => error(&format!("int: cannot convert {}", f)),
+            n => Ok(MalVal::from(Number::from(n.to_bigint()))),
+        },
+    }
+}
+
 fn symbol(a: MalArgs) -> MalRet {
     match a[0] {
         Str(ref s) => Ok(Sym(s.to_string())),
@@ -81,6 +143,69 @@ fn readline(a: MalArgs) -> MalRet {
     }
 }
 
+// Regexes are plain strings, as produced by #"..." literals. A whole
+// regex must match all of the input, not just a prefix of it.
+fn regex(a: &MalArgs, whole: bool) -> Result<Regex, MalErr> {
+    match &a[0] {
+        Str(p) => {
+            let p = if whole { format!(r"\A(?:{})\z", p) } else { p.to_string() };
+            Regex::new(&p).map_err(|e| ErrString(format!("invalid regex: {}", e)))
+        }
+        _ => Err(ErrString("expecting regex string".to_string())),
+    }
+}
+
+// A match without groups is its text; with groups it is [match group1 ...].
+fn re_groups(caps: Captures) -> MalVal {
+    if caps.len() == 1 {
+        return Str(caps[0].to_string());
+    }
+    vector!(caps
+        .iter()
+        .map(|m| m.map_or(Nil, |m| Str(m.as_str().to_string())))
+        .collect())
+}
+
+fn re_find(a: MalArgs) -> MalRet {
+    match &a[1] {
+        Str(s) => Ok(regex(&a, false)?.captures(s).map_or(Nil, re_groups)),
+        _ => error("re-find: expecting string"),
+    }
+}
+
+fn re_matches(a: MalArgs) -> MalRet {
+    match &a[1] {
+        Str(s) => Ok(regex(&a, true)?.captures(s).map_or(Nil, re_groups)),
+        _ => error("re-matches: expecting string"),
+    }
+}
+
+fn re_seq(a: MalArgs) -> MalRet {
+    match &a[1] {
+        Str(s) => {
+            let res: Vec<MalVal> = regex(&a, false)?.captures_iter(s).map(re_groups).collect();
+            Ok(if res.is_empty() { Nil } else { list!(res) })
+        }
+        _ => error("re-seq: expecting string"),
+    }
+}
+
+fn read_string(a: MalArgs) -> MalRet {
+    match (&a[0], a.get(1)) {
+        (Str(s), None) => read_str(s),
+        (Str(s), Some(Str(f))) => read_str_from(s, Some(f)),
+        _ => error("read-string: expecting (str [file]) args"),
+    }
+}
+
+fn read_all_string(a: MalArgs) -> MalRet {
+    match (&a[0], a.get(1)) {
+        (Str(s), None) => Ok(list!(read_all(s, None)?)),
+        (Str(s), Some(Str(f))) => Ok(list!(read_all(s, Some(f))?)),
+        _ => error("read-all-string: expecting (str [file]) args"),
+    }
+}
+
 fn slurp(f: &str) -> MalRet {
     let mut s = String::new();
     match File::open(f).and_then(|mut f| f.read_to_string(&mut s)) {
@@ -99,13 +224,34 @@ fn time_ms(_a: MalArgs) -> MalRet {
     ))
 }
 
+fn format(a: MalArgs) -> MalRet {
+    match a.first() {
+        Some(Str(fmt)) => Ok(Str(printer::format(fmt, &a[1..])?)),
+        _ => error("format: expecting format string"),
+    }
+}
+
+// Exits the process with status n (default 0), which must be 0..=255
+// so that it is not truncated to a different status.
+fn exit(a: MalArgs) -> MalRet {
+    let code = match a.first() {
+        None | Some(Nil) => 0,
+        Some(Int(n)) if (0..=255).contains(n) => *n as i32,
+        Some(Int(n)) => return error(&format!("exit: status {} not in 0 to 255", n)),
+        Some(_) => return error("exit: expecting integer status"),
+    };
+    let _ = std::io::stdout().flush();
+    std::process::exit(code)
+}
+
 fn get(a: MalArgs) -> MalRet {
     match (a[0].clone(), a[1].clone()) {
         (Nil, _) => Ok(Nil),
-        (Hash(ref hm, _), Str(ref s)) => match hm.get(s) {
+        (Hash(ref hm, _), ref k) => match hm.get(k) {
             Some(mv) => Ok(mv.clone()),
             None => Ok(Nil),
         },
+        (Set(ref v, _), ref k) => Ok(v.get(k).cloned().unwrap_or(Nil)),
         _ => error("illegal get args"),
     }
 }
@@ -126,14 +272,15 @@ fn dissoc(a: MalArgs) -> MalRet {
 
 fn contains_q(a: MalArgs) -> MalRet {
     match (a[0].clone(), a[1].clone()) {
-        (Hash(ref hm, _), Str(ref s)) => Ok(Bool(hm.contains_key(s))),
+        (Hash(ref hm, _), ref k) => Ok(Bool(hm.contains_key(k))),
+        (Set(ref v, _), ref k) => Ok(Bool(v.contains(k))),
         _ => error("illegal get args"),
     }
 }
 
 fn keys(a: MalArgs) -> MalRet {
     match a[0] {
-        Hash(ref hm, _) => Ok(list!(hm.keys().map(|k| { Str(k.to_string()) }).collect())),
+        Hash(ref hm, _) => Ok(list!(hm.keys().cloned().collect())),
         _ => error("keys requires Hash Map"),
     }
 }
@@ -145,9 +292,32 @@ fn vals(a: MalArgs) -> MalRet {
     }
 }
 
+fn set(a: MalArgs) -> MalRet {
+    match a[0] {
+        List(ref v, _) | Vector(ref v, _) => Ok(hash_set(v.to_vec())),
+        Set(..) => Ok(a[0].clone()),
+        Nil => Ok(hash_set(vec![])),
+        _ => error("non-seq passed to set"),
+    }
+}
+
+fn disj(a: MalArgs) -> MalRet {
+    match a[0] {
+        Set(ref v, _) => {
+            let mut set = (**v).clone();
+            for k in &a[1..] {
+                set.shift_remove(k);
+            }
+            Ok(Set(Rc::new(set), Rc::new(Nil)))
+        }
+        _ => error("disj on non-set"),
+    }
+}
+
 fn vec(a: MalArgs) -> MalRet {
     match a[0] {
         List(ref v, _) | Vector(ref v, _) => Ok(vector!(v.to_vec())),
+        Set(ref v, _) => Ok(vector!(v.iter().cloned().collect())),
         _ => error("non-seq passed to vec"),
     }
 }
@@ -159,6 +329,7 @@ fn cons(a: MalArgs) -> MalRet {
             new_v.extend_from_slice(&v);
             Ok(list!(new_v.to_vec()))
         }
+        Set(v, _) => Ok(list!([a[0].clone()].into_iter().chain(v.iter().cloned()).collect())),
         _ => error("cons expects seq as second arg"),
     }
 }
@@ -168,6 +339,7 @@ fn concat(a: MalArgs) -> MalRet {
     for seq in a.iter() {
         match seq {
             List(v, _) | Vector(v, _) => new_v.extend_from_slice(v),
+            Set(v, _) => new_v.extend(v.iter().cloned()),
             _ => return error("non-seq passed to concat"),
         }
     }
@@ -182,14 +354,19 @@ fn nth(a: MalArgs) -> MalRet {
             }
             Ok(seq[idx as usize].clone())
         }
+        (Set(set, _), Int(idx)) => match set.get_index(idx as usize) {
+            Some(v) => Ok(v.clone()),
+            None => error("nth: index out of range"),
+        },
         _ => error("invalid args to nth"),
     }
 }
 
 fn first(a: MalArgs) -> MalRet {
     match a[0].clone() {
-        List(ref seq, _) | Vector(ref seq, _) if seq.len() == 0 => Ok(Nil),
+        List(ref seq, _) | Vector(ref seq, _) if seq.is_empty() => Ok(Nil),
         List(ref seq, _) | Vector(ref seq, _) => Ok(seq[0].clone()),
+        Set(ref set, _) => Ok(set.first().cloned().unwrap_or(Nil)),
         Nil => Ok(Nil),
         _ => error("invalid args to first"),
     }
@@ -204,34 +381,33 @@ fn rest(a: MalArgs) -> MalRet {
                 Ok(list![])
             }
         }
+        Set(ref set, _) => Ok(list!(set.iter().skip(1).cloned().collect())),
         Nil => Ok(list![]),
         _ => error("invalid args to first"),
     }
 }
 
 fn apply(a: MalArgs) -> MalRet {
+    let mut fargs = a[1..a.len() - 1].to_vec();
     match a[a.len() - 1] {
-        List(ref v, _) | Vector(ref v, _) => {
-            let f = &a[0];
-            let mut fargs = a[1..a.len() - 1].to_vec();
-            fargs.extend_from_slice(v);
-            f.apply(fargs)
-        }
-        _ => error("apply called with non-seq"),
+        List(ref v, _) | Vector(ref v, _) => fargs.extend_from_slice(v),
+        Set(ref v, _) => fargs.extend(v.iter().cloned()),
+        _ => return error("apply called with non-seq"),
     }
+    a[0].apply(fargs)
 }
 
 fn map(a: MalArgs) -> MalRet {
-    match a[1] {
-        List(ref v, _) | Vector(ref v, _) => {
-            let mut res = vec![];
-            for mv in v.iter() {
-                res.push(a[0].apply(vec![mv.clone()])?)
-            }
-            Ok(list!(res))
-        }
-        _ => error("map called with non-seq"),
+    let items: Vec<MalVal> = match a[1] {
+        List(ref v, _) | Vector(ref v, _) => v.to_vec(),
+        Set(ref v, _) => v.iter().cloned().collect(),
+        _ => return error("map called with non-seq"),
+    };
+    let mut res = vec![];
+    for mv in items {
+        res.push(a[0].apply(vec![mv])?)
     }
+    Ok(list!(res))
 }
 
 fn conj(a: MalArgs) -> MalRet {
@@ -245,25 +421,35 @@ fn conj(a: MalArgs) -> MalRet {
             Ok(list!([&sl[..], v].concat()))
         }
         Vector(ref v, _) => Ok(vector!([v, &a[1..]].concat())),
+        Set(ref v, _) => {
+            let mut set = (**v).clone();
+            set.extend(a[1..].iter().cloned());
+            Ok(Set(Rc::new(set), Rc::new(Nil)))
+        }
         _ => error("conj: called with non-seq"),
     }
 }
 
 fn seq(a: MalArgs) -> MalRet {
     match a[0] {
-        List(ref v, _) | Vector(ref v, _) if v.len() == 0 => Ok(Nil),
+        List(ref v, _) | Vector(ref v, _) if v.is_empty() => Ok(Nil),
         List(ref v, _) | Vector(ref v, _) => Ok(list!(v.to_vec())),
+        Set(ref v, _) if v.is_empty() => Ok(Nil),
+        Set(ref v, _) => Ok(list!(v.iter().cloned().collect())),
+        Hash(ref hm, _) if hm.is_empty() => Ok(Nil),
+        Hash(ref hm, _) => Ok(list!(hm
+            .iter()
+            .map(|(k, v)| vector![k.clone(), v.clone()])
+            .collect())),
         Str(ref s) if s.is_empty() => Ok(Nil),
-        Str(ref s) if !a[0].keyword_q() => {
-            Ok(list!(s.chars().map(|c| { Str(c.to_string()) }).collect()))
-        }
+        Str(ref s) if !a[0].keyword_q() => Ok(list!(s.chars().map(Char).collect())),
         Nil => Ok(Nil),
         _ => error("seq: called with non-seq"),
     }
 }
 
 pub fn ns() -> Vec<(&'static str, MalVal)> {
-    vec![
+    let mut ns = vec![
         ("=", func(|a| Ok(Bool(a[0] == a[1])))),
         ("throw", func(|a| Err(ErrMalVal(a[0].clone())))),
         ("nil?", func(fn_is_type!(Nil))),
@@ -276,11 +462,19 @@ pub fn ns() -> Vec<(&'static str, MalVal)> {
             func(fn_is_type!(Str(ref s) if !s.starts_with('\u{29e}'))),
         ),
         ("keyword", func(|a| a[0].keyword())),
+        ("char", func(char)),
+        ("char?", func(fn_is_type!(Char(_)))),
+        ("int", func(int)),
         (
             "keyword?",
             func(fn_is_type!(Str(ref s) if s.starts_with('\u{29e}'))),
         ),
-        ("number?", func(fn_is_type!(Int(_)))),
+        ("number?", func(fn_is_type!(Int(_), BigInt(_), Ratio(_), Float(_)))),
+        ("ratio?", func(fn_is_type!(Ratio(_)))),
+        ("rational?", func(fn_is_type!(Int(_), BigInt(_), Ratio(_)))),
+        ("numerator", func(fn_num!(Number::numerator))),
+        ("denominator", func(fn_num!(Number::denominator))),
+        ("rationalize", func(fn_num!(Number::rationalize))),
         (
             "fn?",
             func(fn_is_type!(MalFunc{is_macro,..} if !is_macro,Func(_,_))),
@@ -291,6 +485,7 @@ pub fn ns() -> Vec<(&'static str, MalVal)> {
         ),
         ("pr-str", func(|a| Ok(Str(pr_seq(&a, true, "", "", " "))))),
         ("str", func(|a| Ok(Str(pr_seq(&a, false, "", "", ""))))),
+        ("format", func(format)),
         (
             "prn",
             func(|a| {
@@ -305,18 +500,24 @@ pub fn ns() -> Vec<(&'static str, MalVal)> {
                 Ok(Nil)
             }),
         ),
-        ("read-string", func(fn_str!(read_str))),
+        ("read-string", func(read_string)),
+        ("read-all-string", func(read_all_string)),
+        ("re-pattern", func(|a| regex(&a, false).map(|_| a[0].clone()))),
+        ("re-find", func(re_find)),
+        ("re-matches", func(re_matches)),
+        ("re-seq", func(re_seq)),
         ("readline", func(readline)),
         ("slurp", func(fn_str!(slurp))),
-        ("<", func(fn_t_num_num!(Bool, |i, j| { i < j }))),
-        ("<=", func(fn_t_num_num!(Bool, |i, j| { i <= j }))),
-        (">", func(fn_t_num_num!(Bool, |i, j| { i > j }))),
-        (">=", func(fn_t_num_num!(Bool, |i, j| { i >= j }))),
-        ("+", func(fn_t_num_num!(Int, |i, j| { i + j }))),
-        ("-", func(fn_t_num_num!(Int, |i, j| { i - j }))),
-        ("*", func(fn_t_num_num!(Int, |i, j| { i * j }))),
-        ("/", func(fn_t_num_num!(Int, |i, j| { i / j }))),
+        ("<", func(fn_num_cmp!(|i, j| { i < j }))),
+        ("<=", func(fn_num_cmp!(|i, j| { i <= j }))),
+        (">", func(fn_num_cmp!(|i, j| { i > j }))),
+        (">=", func(fn_num_cmp!(|i, j| { i >= j }))),
+        ("+", func(fn_num_fold!(0, |i, j| { Ok(i + j) }))),
+        ("-", func(fn_num_fold!(0, |i, j| { Ok(i - j) }))),
+        ("*", func(fn_num_fold!(1, |i, j| { Ok(i * j) }))),
+        ("/", func(fn_num_fold!(1, Number::checked_div))),
         ("time-ms", func(time_ms)),
+        ("exit", func(exit)),
         ("sequential?", func(fn_is_type!(List(_, _), Vector(_, _)))),
         ("list", func(|a| Ok(list!(a.to_vec())))),
         ("list?", func(fn_is_type!(List(_, _)))),
@@ -324,6 +525,10 @@ pub fn ns() -> Vec<(&'static str, MalVal)> {
         ("vector?", func(fn_is_type!(Vector(_, _)))),
         ("hash-map", func(hash_map)),
         ("map?", func(fn_is_type!(Hash(_, _)))),
+        ("hash-set", func(|a| Ok(hash_set(a)))),
+        ("set", func(set)),
+        ("set?", func(fn_is_type!(Set(_, _)))),
+        ("disj", func(disj)),
         ("assoc", func(assoc)),
         ("dissoc", func(dissoc)),
         ("get", func(get)),
@@ -349,5 +554,42 @@ pub fn ns() -> Vec<(&'static str, MalVal)> {
         ("deref", func(|a| a[0].deref())),
         ("reset!", func(|a| a[0].reset_bang(&a[1]))),
         ("swap!", func(|a| a[0].swap_bang(&a[1..].to_vec()))),
-    ]
-}
\ No newline at end of file
+    ];
+    ns.extend(math::ns());
+    ns.extend(random::ns());
+    ns.extend(pprint::ns());
+    ns
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seq_functions_take_sets() {
+        let s = || hash_set(vec![Int(1), Int(2)]);
+        assert!(matches!(first(vec![s()]), Ok(Int(1))));
+        assert_eq!(rest(vec![s()]).ok().unwrap().pr_str(true), "(2)");
+        assert!(matches!(nth(vec![s(), Int(1)]), Ok(Int(2))));
+        assert!(nth(vec![s(), Int(2)]).is_err());
+        assert_eq!(cons(vec![Int(0), s()]).ok().unwrap().pr_str(true), "(0 1 2)");
+        assert_eq!(concat(vec![s(), s()]).ok().unwrap().pr_str(true), "(1 2 1 2)");
+        let add = ns().into_iter().find(|(name, _)| *name == "+").unwrap().1;
+        assert!(matches!(apply(vec![add, s()]), Ok(Int(3))));
+    }
+
+    #[test]
+    fn test_re_matches_whole_input() {
+        let matches = |re: &str, s: &str| re_matches(vec![Str(re.to_string()), Str(s.to_string())]);
+        assert!(matches!(matches("a|ab", "ab"), Ok(Str(m)) if m == "ab"));
+        assert!(matches!(matches(r"\d+?", "123"), Ok(Str(m)) if m == "123"));
+        assert!(matches!(matches("a", "ab"), Ok(Nil)));
+        assert!(matches!(matches("(a)(b)?", "a"), Ok(Vector(..))));
+    }
+
+    #[test]
+    fn test_exit_status_range() {
+        assert!(exit(vec![Int(256)]).is_err());
+        assert!(exit(vec![Int(-1)]).is_err());
+        assert!(exit(vec![Str("1".to_string())]).is_err());
+    }
+}
@@ -55,6 +55,17 @@ pub fn env_get(env: &Env, key: &str) -> Option<MalVal> {
   }
 }
 
+// Every name visible from env, innermost scope first; shadowed names repeat.
+pub fn env_keys(env: &Env) -> Vec<String> {
+  let mut keys = vec![];
+  let mut mut_env = Some(env);
+  while let Some(e) = mut_env {
+    keys.extend(e.data.borrow().keys().cloned());
+    mut_env = e.outer.as_ref();
+  }
+  keys
+}
+
 pub fn env_find_repl(env: &Env) -> Env {
   let mut mut_env = env;
   while let Some(outer) = &mut_env.outer {
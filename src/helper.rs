@@ -0,0 +1,331 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, ConditionalEventHandler, Context, Event, EventContext, Helper, RepeatCount};
+
+use crate::env::{env_keys, Env};
+use crate::reader::{is_incomplete, token_spans};
+use crate::rep::SPECIAL_FORMS;
+
+// Line editing support for the REPL.
+pub struct MalHelper {
+    env: Env,
+    // Cleared for the final redraw of an accepted line.
+    match_brackets: Cell<bool>,
+}
+
+impl MalHelper {
+    pub fn new(env: Env) -> MalHelper {
+        MalHelper {
+            env,
+            match_brackets: Cell::new(true),
+        }
+    }
+
+    // Returns where the completed word starts and the candidates for it.
+    fn complete_at(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let line = &line[..pos];
+        if let Some(start) = open_string(line) {
+            return complete_path(line, start);
+        }
+        let start = line
+            .rfind(|c: char| c.is_whitespace() || "()[]{}'`~@^,\"".contains(c))
+            .map_or(0, |i| i + 1);
+        let prefix = &line[start..];
+        let mut names: Vec<String> = SPECIAL_FORMS
+            .iter()
+            .map(|s| s.to_string())
+            .chain(env_keys(&self.env))
+            .filter(|s| s.starts_with(prefix))
+            .collect();
+        names.sort();
+        names.dedup();
+        (start, names)
+    }
+}
+
+// Byte offset just past the opening quote if line ends inside a string.
+fn open_string(line: &str) -> Option<usize> {
+    let mut start = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (c, start) {
+            ('\\', Some(_)) => {
+                chars.next();
+            }
+            ('"', Some(_)) => start = None,
+            ('"', None) => start = Some(i + 1),
+            (';', None) => {
+                // A comment runs to the end of its line.
+                chars.by_ref().find(|&(_, c)| c == '\n');
+            }
+            _ => {}
+        }
+    }
+    start
+}
+
+// Completes the last path component of the string contents line[start..].
+fn complete_path(line: &str, start: usize) -> (usize, Vec<String>) {
+    let typed = &line[start..];
+    let (dir, prefix) = match typed.rfind('/') {
+        Some(i) => (&typed[..=i], &typed[i + 1..]),
+        None => ("", typed),
+    };
+    let path = if dir.is_empty() { Path::new(".") } else { Path::new(dir) };
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return (start, vec![]),
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let is_dir = e.path().is_dir();
+            Some(format!("{}{}{}", dir, name, if is_dir { "/" } else { "" }))
+        })
+        .collect();
+    names.sort();
+    (start, names)
+}
+
+impl Helper for MalHelper {}
+
+impl Completer for MalHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.complete_at(line, pos))
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+}
+
+// ANSI SGR parameters for each kind of token.
+const STRING_COLOR: &str = "32";
+const NUMBER_COLOR: &str = "33";
+const KEYWORD_COLOR: &str = "36";
+const COMMENT_COLOR: &str = "90";
+const SPECIAL_COLOR: &str = "35";
+const BRACKET_COLOR: &str = "1;34";
+
+fn token_color(tok: &str) -> Option<&'static str> {
+    let mut chars = tok.chars();
+    let c0 = chars.next()?;
+    let c1 = chars.next();
+    match c0 {
+        '"' | '\\' => Some(STRING_COLOR),
+        '#' if c1 == Some('"') => Some(STRING_COLOR),
+        ';' => Some(COMMENT_COLOR),
+        ':' => Some(KEYWORD_COLOR),
+        '0'..='9' => Some(NUMBER_COLOR),
+        '+' | '-' | '.' if c1.is_some_and(|c| c.is_ascii_digit()) => Some(NUMBER_COLOR),
+        _ if matches!(tok, "nil" | "true" | "false") => Some(NUMBER_COLOR),
+        _ if SPECIAL_FORMS.contains(&tok) => Some(SPECIAL_COLOR),
+        _ => None,
+    }
+}
+
+// The bracket kind of an opening or closing token, and whether it opens.
+fn bracket(tok: &str) -> Option<(char, bool)> {
+    match tok {
+        "(" | "#(" => Some(('(', true)),
+        "[" => Some(('[', true)),
+        "{" | "#{" => Some(('{', true)),
+        ")" => Some(('(', false)),
+        "]" => Some(('[', false)),
+        "}" => Some(('{', false)),
+        _ => None,
+    }
+}
+
+// Indices of the bracket token under (or just before) the cursor and of
+// the token that matches it. Brackets in strings and comments are ignored.
+fn matching_bracket(line: &str, spans: &[Range<usize>], pos: usize) -> Option<(usize, usize)> {
+    let is_bracket = |i: &usize| bracket(&line[spans[*i].clone()]).is_some();
+    let i = (0..spans.len())
+        .find(|&i| spans[i].contains(&pos))
+        .filter(is_bracket)
+        .or_else(|| (0..spans.len()).find(|&i| spans[i].end == pos).filter(is_bracket))?;
+    let (kind, open) = bracket(&line[spans[i].clone()])?;
+    let order: Vec<usize> = if open {
+        (i + 1..spans.len()).collect()
+    } else {
+        (0..i).rev().collect()
+    };
+    let mut depth = 0;
+    for j in order {
+        match bracket(&line[spans[j].clone()]) {
+            Some((_, o)) if o == open => depth += 1,
+            Some((k, _)) if depth == 0 => return (k == kind).then_some((i, j)),
+            Some(_) => depth -= 1,
+            None => {}
+        }
+    }
+    None
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let spans = token_spans(line);
+        let matched = match self.match_brackets.get() {
+            true => matching_bracket(line, &spans, pos),
+            false => None,
+        };
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for (i, span) in spans.iter().enumerate() {
+            out.push_str(&line[last..span.start]);
+            let tok = &line[span.clone()];
+            let color = match matched {
+                Some((a, b)) if i == a || i == b => Some(BRACKET_COLOR),
+                _ => token_color(tok),
+            };
+            match color {
+                Some(c) => out.push_str(&format!("\x1b[{}m{}\x1b[0m", c, tok)),
+                None => out.push_str(tok),
+            }
+            last = span.end;
+        }
+        out.push_str(&line[last..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        self.match_brackets.set(kind != CmdKind::ForcedRefresh);
+        true
+    }
+}
+
+// Enter inside an unfinished form starts a new line instead of submitting,
+// so a multi-line form is read, and stored in the history, as one entry.
+// rustyline 15 has no way to show a prompt on continuation lines, so
+// ContinueLine indents them instead.
+impl Validator for MalHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+// The text Enter at the end of line inserts if line is unfinished: a
+// newline and two spaces per unclosed bracket. Inside a string only the
+// newline is added, since spaces would become part of the string.
+fn continuation(line: &str) -> Option<String> {
+    if !is_incomplete(line) {
+        return None;
+    }
+    if open_string(line).is_some() {
+        return Some("\n".to_string());
+    }
+    let depth = token_spans(line).iter().fold(0usize, |depth, span| {
+        match bracket(&line[span.clone()]) {
+            Some((_, true)) => depth + 1,
+            Some((_, false)) => depth.saturating_sub(1),
+            None => depth,
+        }
+    });
+    Some(format!("\n{}", "  ".repeat(depth)))
+}
+
+// Bound to Enter: indents the next line of an unfinished form. Anywhere
+// but the end of the input, Enter falls back to the validator.
+pub struct ContinueLine;
+
+impl ConditionalEventHandler for ContinueLine {
+    fn handle(&self, _evt: &Event, _n: RepeatCount, _positive: bool, ctx: &EventContext) -> Option<Cmd> {
+        if ctx.pos() != ctx.line().len() {
+            return None;
+        }
+        continuation(ctx.line()).map(|text| Cmd::Insert(1, text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{env_new, env_sets};
+    use crate::types::MalVal::Nil;
+
+    fn helper() -> MalHelper {
+        let outer = env_new(None);
+        env_sets(&outer, "println", Nil);
+        env_sets(&outer, "prn", Nil);
+        let inner = env_new(Some(outer));
+        env_sets(&inner, "prn", Nil);
+        MalHelper::new(inner)
+    }
+
+    #[test]
+    fn test_complete_symbols() {
+        let h = helper();
+        assert_eq!(h.complete_at("(pr", 3), (1, vec!["println".to_string(), "prn".to_string()]));
+        assert_eq!(h.complete_at("(let* [x 1] (de", 15), (13, vec!["def!".to_string(), "defmacro!".to_string()]));
+        assert_eq!(h.complete_at("(fn* (x) x)", 3).1, vec!["fn*".to_string()]);
+    }
+
+    #[test]
+    fn test_open_string() {
+        assert_eq!(open_string("(slurp \"src/"), Some(8));
+        assert_eq!(open_string("(str \"a\\\"b\" x"), None);
+        assert_eq!(open_string("; \"comment\n(x"), None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let h = helper();
+        h.highlight_char("", 0, CmdKind::ForcedRefresh);
+        assert_eq!(
+            h.highlight("(if x \"s\" 1) ; c", 0),
+            "(\x1b[35mif\x1b[0m x \x1b[32m\"s\"\x1b[0m \x1b[33m1\x1b[0m) \x1b[90m; c\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let line = "(a [b \")\"] #{c})";
+        let spans = token_spans(line);
+        assert_eq!(matching_bracket(line, &spans, 0), Some((0, 9)));
+        assert_eq!(matching_bracket(line, &spans, line.len()), Some((9, 0)));
+        assert_eq!(matching_bracket(line, &spans, 3), Some((2, 5)));
+        assert_eq!(matching_bracket(line, &spans, 12), Some((6, 8)));
+        assert_eq!(matching_bracket(line, &spans, 2), None);
+        assert_eq!(matching_bracket("(]", &token_spans("(]"), 0), None);
+    }
+
+    #[test]
+    fn test_continuation() {
+        assert_eq!(continuation("(+ 1 2)"), None);
+        assert_eq!(continuation("(do (a)"), Some("\n  ".to_string()));
+        assert_eq!(continuation("(let* [x {:a"), Some("\n      ".to_string()));
+        assert_eq!(continuation("(str \"a ("), Some("\n".to_string()));
+    }
+
+    #[test]
+    fn test_complete_path() {
+        let h = helper();
+        let (start, names) = h.complete_at("(load-file \"src/he", 18);
+        assert_eq!(start, 12);
+        assert_eq!(names, vec!["src/helper.rs".to_string()]);
+    }
+}
@@ -1,86 +1,199 @@
 #![allow(non_snake_case)]
 
+use std::io::{IsTerminal, Read};
 use std::rc::Rc;
 
 
 #[macro_use]
 extern crate lazy_static;
 extern crate fnv;
+extern crate indexmap;
 extern crate itertools;
 extern crate regex;
 
 extern crate rustyline;
-use rustyline::config::Configurer;
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
 
 #[macro_use]
 mod types;
 use crate::types::MalVal::{List, Nil, Str};
-use crate::types::format_error;
+use crate::types::MalErr::ErrString;
+use crate::types::{format_error, MalRet, MalVal};
+mod config;
 mod env;
+mod helper;
 mod printer;
 mod reader;
 use crate::env::{env_new, env_sets};
 #[macro_use]
 mod core;
 mod rep;
+mod repl;
+mod math;
 mod number;
-use crate::rep::{rep, re};
+mod pprint;
+mod random;
+use crate::rep::{eval_all, re};
+use crate::repl::Repl;
 
-fn main() {
-    let mut args = std::env::args();
-    let arg1 = args.nth(1);
+const USAGE: &str = "usage: mal [--no-init] [--init file] [--history file] [-e expr | - | file] [args...]";
+
+// What to run once the environment is set up.
+enum Mode {
+    Repl,
+    Eval(String),
+    Stdin,
+    File(String),
+}
+
+struct Options {
+    mode: Mode,
+    no_init: bool,
+    init: Option<String>,
+    history: Option<String>,
+}
 
-    let mut rl = Editor::<(), rustyline::history::DefaultHistory>::new().unwrap();
-    if rl.load_history(".mal-history").is_err() {
-        eprintln!("No previous history");
+// Splits the command line into options and the arguments for *ARGV*.
+fn parse_args(args: &[String]) -> Result<(Options, &[String]), String> {
+    let mut opts = Options {
+        mode: Mode::Repl,
+        no_init: false,
+        init: None,
+        history: None,
+    };
+    let mut i = 0;
+    loop {
+        let value = |i: usize| match args.get(i + 1) {
+            Some(v) => Ok(v.clone()),
+            None => Err(format!("{}: expecting an argument", args[i])),
+        };
+        match args.get(i).map(String::as_str) {
+            None => return Ok((opts, &args[i..])),
+            Some("--no-init") => opts.no_init = true,
+            Some("--init") => opts.init = Some(value(i)?),
+            Some("--history") => opts.history = Some(value(i)?),
+            Some("-e") => {
+                opts.mode = Mode::Eval(value(i)?);
+                return Ok((opts, &args[i + 2..]));
+            }
+            Some("-") => {
+                opts.mode = Mode::Stdin;
+                return Ok((opts, &args[i + 1..]));
+            }
+            Some("-h") | Some("--help") => return Err(USAGE.to_string()),
+            Some(opt) if opt.starts_with('-') => return Err(format!("unknown option {}\n{}", opt, USAGE)),
+            Some(file) => {
+                opts.mode = Mode::File(file.to_string());
+                return Ok((opts, &args[i + 1..]));
+            }
+        }
+        i += if matches!(args[i].as_str(), "--init" | "--history") { 2 } else { 1 };
     }
+}
+
+// Prints an uncaught error and exits nonzero.
+fn exit_on_error(res: MalRet) -> MalVal {
+    res.unwrap_or_else(|e| {
+        eprintln!("Error: {}", format_error(e));
+        std::process::exit(1)
+    })
+}
 
-    rl.set_color_mode(rustyline::ColorMode::Enabled);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (opts, argv) = parse_args(&args).unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        std::process::exit(2)
+    });
 
     let repl_env = env_new(None);
     for (k, v) in core::ns() {
         env_sets(&repl_env, k, v);
     }
-    env_sets(&repl_env, "*ARGV*", list!(args.map(Str).collect()));
+    printer::set_print_env(&repl_env);
+    env_sets(&repl_env, "*ARGV*", list!(argv.iter().cloned().map(Str).collect()));
 
     re("(def! *host-language* \"rust\")", &repl_env);
     re("(def! not (fn* (a) (if a false true)))", &repl_env);
     re(
-        "(def! load-file (fn* (f) (eval (read-string (str \"(do \" (slurp f) \"\nnil)\")))))",
+        "(def! load-string (fn* (s) (eval (cons 'do (read-all-string s)))))",
+        &repl_env,
+    );
+    re(
+        "(def! load-file (fn* (f) (do (eval (cons 'do (read-all-string (slurp f) f))) nil)))",
         &repl_env,
     );
     re("(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw \"odd number of forms to cond\")) (cons 'cond (rest (rest xs)))))))",
         &repl_env);
 
-    if let Some(f) = arg1 {
-        re(&format!("(load-file \"{}\")", f), &repl_env);
-        std::process::exit(0);
-    }
-
-    re("(println (str \"Mal [\" *host-language* \"]\"))", &repl_env);
-    let mut num = 0;
-    loop {
-        let readline = rl.readline("user> ");
-        match readline {
-            Ok(line) => {
-                let _ = rl.add_history_entry(&line);
-                rl.save_history(".mal-history").unwrap();
-                if !line.is_empty() {
-                    match rep(&line, &repl_env, &num) {
-                        Ok(out) => println!("{}", out),
-                        Err(e) => println!("Error: {}", format_error(e)),
-                    }
+    match opts.mode {
+        Mode::Eval(expr) => match exit_on_error(eval_all(&expr, None, &repl_env)) {
+            Nil => {}
+            v => println!("{}", v.pr_str(true)),
+        },
+        Mode::Stdin => {
+            let mut src = String::new();
+            if let Err(e) = std::io::stdin().read_to_string(&mut src) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+            exit_on_error(eval_all(&src, Some("<stdin>"), &repl_env));
+        }
+        Mode::File(f) => {
+            let src = std::fs::read_to_string(&f).unwrap_or_else(|e| {
+                eprintln!("Error: {}: {}", f, e);
+                std::process::exit(1)
+            });
+            exit_on_error(eval_all(&src, Some(&f), &repl_env));
+        }
+        Mode::Repl => {
+            let init = config::init_file(opts.init.as_deref(), &config::os_vars);
+            if let Some(f) = init.filter(|_| !opts.no_init) {
+                let f = f.to_string_lossy();
+                let res = std::fs::read_to_string(&*f)
+                    .map_err(|e| ErrString(e.to_string()))
+                    .and_then(|src| eval_all(&src, Some(&f), &repl_env));
+                if let Err(e) = res {
+                    eprintln!("Error loading {}: {}", f, format_error(e));
                 }
-                num += 1;
             }
-            Err(ReadlineError::Interrupted) => continue,
-            Err(ReadlineError::Eof) => break,
-            Err(err) => {
-                println!("{:?}", err);
-                break;
+            let interactive = std::io::stdin().is_terminal();
+            if interactive {
+                re("(println (str \"Mal [\" *host-language* \"]\"))", &repl_env);
             }
+            let history = config::history_file(opts.history.as_deref(), &config::os_vars);
+            Repl::new(repl_env, interactive, history).run();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = strings(&["-e", "(+ 1 2)", "a"]);
+        assert!(matches!(parse_args(&args), Ok((Options { mode: Mode::Eval(e), .. }, [a])) if e == "(+ 1 2)" && a == "a"));
+        let args = strings(&["f.mal", "-e"]);
+        assert!(matches!(parse_args(&args), Ok((Options { mode: Mode::File(f), .. }, [a])) if f == "f.mal" && a == "-e"));
+        assert!(matches!(parse_args(&strings(&["-"])), Ok((Options { mode: Mode::Stdin, .. }, []))));
+        assert!(matches!(parse_args(&[]), Ok((Options { mode: Mode::Repl, .. }, []))));
+        assert!(parse_args(&strings(&["-e"])).is_err());
+        assert!(parse_args(&strings(&["-x"])).is_err());
+        assert!(parse_args(&strings(&["--init"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let args = strings(&["--no-init", "--history", "h", "--init", "i.mal", "-", "x"]);
+        let (opts, argv) = parse_args(&args).unwrap();
+        assert!(opts.no_init && matches!(opts.mode, Mode::Stdin));
+        assert_eq!(opts.history.as_deref(), Some("h"));
+        assert_eq!(opts.init.as_deref(), Some("i.mal"));
+        assert_eq!(argv, ["x"]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::f64::consts;
+
+use num_bigint::{BigInt, Sign};
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use crate::number::{arithmetic_error, Number};
+use crate::types::MalErr::ErrString;
+use crate::types::MalVal::{Bool, Float, Int};
+use crate::types::{error, func, MalArgs, MalErr, MalRet, MalVal};
+
+macro_rules! fn_f64 {
+    ($name:expr, $fn:expr) => {{
+        |a: MalArgs| float_call($name, &a, |x| $fn(x[0]))
+    }};
+}
+
+macro_rules! fn_f64_f64 {
+    ($name:expr, $fn:expr) => {{
+        |a: MalArgs| float_call($name, &a, |x| $fn(x[0], x[1]))
+    }};
+}
+
+macro_rules! fn_round {
+    ($name:expr, $fn:expr, $ratio_fn:expr) => {{
+        |a: MalArgs| round($name, &a[0], $fn, $ratio_fn)
+    }};
+}
+
+// Exact results larger than this many bits are refused rather than
+// computed, since building them can exhaust memory.
+const MAX_BITS: u64 = 1 << 24;
+
+fn num(name: &str, mv: &MalVal) -> Result<Number, MalErr> {
+    Number::try_from(mv).map_err(|_| {
+        ErrString(format!("{}: expecting number, got {}", name, mv.pr_str(true)))
+    })
+}
+
+// A NaN result from non-NaN arguments means the arguments were outside
+// the function's domain.
+fn float_call(name: &str, a: &MalArgs, f: impl Fn(&[f64]) -> f64) -> MalRet {
+    let mut xs = vec![];
+    for mv in a.iter() {
+        xs.push(num(name, mv)?.as_f64());
+    }
+    let res = f(&xs);
+    if res.is_nan() && !xs.iter().any(|x| x.is_nan()) {
+        return error(&format!("{}: argument out of domain", name));
+    }
+    Ok(Float(res))
+}
+
+fn log_call(name: &str, a: &MalArgs, f: fn(f64) -> f64) -> MalRet {
+    if num(name, &a[0])? <= Number::Int(0) {
+        return error(&format!("{}: argument must be positive", name));
+    }
+    float_call(name, a, |x| f(x[0]))
+}
+
+// Rounds floats and ratios to an integer with f; integers are unchanged.
+fn round(
+    name: &str,
+    mv: &MalVal,
+    f: fn(f64) -> f64,
+    ratio_f: fn(&BigRational) -> BigRational,
+) -> MalRet {
+    match num(name, mv)? {
+        n @ (Number::Int(_) | Number::BigInt(_)) => Ok(MalVal::from(n)),
+        Number::Ratio(r) => Ok(MalVal::from(Number::from(ratio_f(&r).to_integer()))),
+        Number::Float(x) => match BigInt::from_f64(f(x)) {
+            Some(n) => Ok(MalVal::from(Number::from(n))),
+            None => error(&format!("{}: cannot round {}", name, x)),
+        },
+    }
+}
+
+fn abs(a: MalArgs) -> MalRet {
+    let n = num("abs", &a[0])?;
+    if n < Number::Int(0) {
+        Ok(MalVal::from(-n))
+    } else {
+        Ok(MalVal::from(n))
+    }
+}
+
+fn sqrt(a: MalArgs) -> MalRet {
+    if num("sqrt", &a[0])? < Number::Int(0) {
+        return error("sqrt: argument must be non-negative");
+    }
+    float_call("sqrt", &a, |x| x[0].sqrt())
+}
+
+// Integer and ratio bases raised to integer powers stay exact, as long as
+// the result fits in MAX_BITS. Zero to a negative power is an error
+// whatever the types, rather than ##Inf from the float fallback.
+fn pow(a: MalArgs) -> MalRet {
+    let (base, exp) = (num("pow", &a[0])?, num("pow", &a[1])?);
+    if base == Number::Int(0) && exp < Number::Int(0) {
+        return Err(arithmetic_error("pow: zero raised to a negative power"));
+    }
+    match (&base, &exp) {
+        (Number::Int(_) | Number::BigInt(_) | Number::Ratio(_), Number::Int(e)) => {
+            let base = base.to_ratio();
+            let bits = base.numer().bits().max(base.denom().bits()) - 1;
+            if bits.saturating_mul(e.unsigned_abs()) > MAX_BITS {
+                return Err(arithmetic_error("pow: result too large"));
+            }
+            let r = num_traits::pow(base, e.unsigned_abs() as usize);
+            let n = Number::from(r);
+            if *e < 0 {
+                Ok(MalVal::from(Number::Int(1) / n))
+            } else {
+                Ok(MalVal::from(n))
+            }
+        }
+        _ => float_call("pow", &a, |x| x[0].powf(x[1])),
+    }
+}
+
+fn extremum(a: MalArgs, name: &str, pick: fn(&Number, &Number) -> bool) -> MalRet {
+    if a.is_empty() {
+        return error(&format!("{}: expecting at least one argument", name));
+    }
+    let mut best = num(name, &a[0])?;
+    for mv in a[1..].iter() {
+        let n = num(name, mv)?;
+        if pick(&n, &best) {
+            best = n;
+        }
+    }
+    Ok(MalVal::from(best))
+}
+
+fn divisor(name: &str, mv: &MalVal) -> Result<Number, MalErr> {
+    let d = num(name, mv)?;
+    if d == Number::Int(0) {
+        return Err(arithmetic_error(&format!("{}: divide by zero", name)));
+    }
+    Ok(d)
+}
+
+// Truncating division.
+fn quot(a: MalArgs) -> MalRet {
+    let (n, d) = (num("quot", &a[0])?, divisor("quot", &a[1])?);
+    match (n, d) {
+        (n @ Number::Float(_), d) | (n, d @ Number::Float(_)) => {
+            Ok(Float((n.as_f64() / d.as_f64()).trunc()))
+        }
+        (n, d) => Ok(MalVal::from(Number::from((n / d).to_bigint()))),
+    }
+}
+
+// Remainder of truncating division; takes the sign of the dividend.
+fn rem(a: MalArgs) -> MalRet {
+    let (n, d) = (num("rem", &a[0])?, divisor("rem", &a[1])?);
+    Ok(MalVal::from(n % d))
+}
+
+// Remainder of floored division; takes the sign of the divisor.
+fn modulo(a: MalArgs) -> MalRet {
+    let (n, d) = (num("mod", &a[0])?, divisor("mod", &a[1])?);
+    let r = n % d.clone();
+    let zero = Number::Int(0);
+    if r != zero && (r < zero) != (d < zero) {
+        Ok(MalVal::from(r + d))
+    } else {
+        Ok(MalVal::from(r))
+    }
+}
+
+// The unchecked-* variants work on 64-bit ints and wrap on overflow
+// instead of promoting to big integers.
+macro_rules! fn_unchecked {
+    ($name:expr, $fn:expr) => {{
+        |a: MalArgs| Ok(Int($fn(long($name, &a[0])?)))
+    }};
+    ($name:expr, $fn:expr, 2) => {{
+        |a: MalArgs| Ok(Int($fn(long($name, &a[0])?, long($name, &a[1])?)))
+    }};
+}
+
+fn long(name: &str, mv: &MalVal) -> Result<i64, MalErr> {
+    match mv {
+        Int(n) => Ok(*n),
+        _ => Err(ErrString(format!("{}: expecting 64-bit integer, got {}", name, mv.pr_str(true)))),
+    }
+}
+
+fn unchecked_divide(a: MalArgs, name: &str, f: fn(i64, i64) -> i64) -> MalRet {
+    let (n, d) = (long(name, &a[0])?, long(name, &a[1])?);
+    if d == 0 {
+        return Err(arithmetic_error(&format!("{}: divide by zero", name)));
+    }
+    Ok(Int(f(n, d)))
+}
+
+// Bitwise operators only accept integers; floats and ratios are rejected
+// rather than truncated.
+fn integer(name: &str, mv: &MalVal) -> Result<Number, MalErr> {
+    match num(name, mv)? {
+        n @ (Number::Int(_) | Number::BigInt(_)) => Ok(n),
+        _ => Err(ErrString(format!("{}: expecting integer, got {}", name, mv.pr_str(true)))),
+    }
+}
+
+// Shift counts and bit positions are capped at MAX_BITS so that
+// (bit-shift-left 1 n) cannot ask for an enormous allocation.
+fn bit_index(name: &str, mv: &MalVal) -> Result<usize, MalErr> {
+    match integer(name, mv)?.to_bigint().to_u64() {
+        Some(i) if i <= MAX_BITS => Ok(i as usize),
+        _ => Err(ErrString(format!("{}: invalid bit index {}", name, mv.pr_str(true)))),
+    }
+}
+
+fn bit_fold(a: MalArgs, name: &str, f: fn(Number, Number) -> Number) -> MalRet {
+    if a.len() < 2 {
+        return error(&format!("{}: expecting at least two arguments", name));
+    }
+    let mut acc = integer(name, &a[0])?;
+    for mv in a[1..].iter() {
+        acc = f(acc, integer(name, mv)?);
+    }
+    Ok(MalVal::from(acc))
+}
+
+fn bit_not(a: MalArgs) -> MalRet {
+    Ok(MalVal::from(-integer("bit-not", &a[0])? - Number::Int(1)))
+}
+
+fn bit_shift(a: MalArgs, name: &str, f: fn(Number, Number) -> Number) -> MalRet {
+    let n = integer(name, &a[0])?;
+    let i = bit_index(name, &a[1])?;
+    Ok(MalVal::from(f(n, Number::Int(i as i64))))
+}
+
+// Treats n as a 64-bit two's complement value and shifts in zeros.
+fn unsigned_bit_shift_right(a: MalArgs) -> MalRet {
+    let name = "unsigned-bit-shift-right";
+    let n = match integer(name, &a[0])? {
+        Number::Int(n) => n,
+        _ => return error(&format!("{}: expecting 64-bit integer", name)),
+    };
+    match bit_index(name, &a[1])? {
+        i if i < 64 => Ok(Int(((n as u64) >> i) as i64)),
+        _ => error(&format!("{}: shift must be less than 64", name)),
+    }
+}
+
+fn single_bit(name: &str, mv: &MalVal) -> Result<Number, MalErr> {
+    Ok(Number::Int(1) << Number::Int(bit_index(name, mv)? as i64))
+}
+
+fn bit_test(a: MalArgs) -> MalRet {
+    let bit = single_bit("bit-test", &a[1])?;
+    Ok(Bool((integer("bit-test", &a[0])? & bit) != Number::Int(0)))
+}
+
+fn bit_set(a: MalArgs) -> MalRet {
+    let bit = single_bit("bit-set", &a[1])?;
+    Ok(MalVal::from(integer("bit-set", &a[0])? | bit))
+}
+
+fn bit_clear(a: MalArgs) -> MalRet {
+    let bit = single_bit("bit-clear", &a[1])?;
+    let mask = -bit - Number::Int(1);
+    Ok(MalVal::from(integer("bit-clear", &a[0])? & mask))
+}
+
+fn bit_flip(a: MalArgs) -> MalRet {
+    let bit = single_bit("bit-flip", &a[1])?;
+    Ok(MalVal::from(integer("bit-flip", &a[0])? ^ bit))
+}
+
+// Negative ints are counted as 64-bit two's complement; negative big
+// integers have infinitely many set bits.
+fn bit_count(a: MalArgs) -> MalRet {
+    match integer("bit-count", &a[0])? {
+        Number::Int(n) => Ok(Int(n.count_ones() as i64)),
+        Number::BigInt(n) if n.sign() != Sign::Minus => Ok(Int(n.magnitude().count_ones() as i64)),
+        _ => error("bit-count: negative big integer"),
+    }
+}
+
+pub fn ns() -> Vec<(&'static str, MalVal)> {
+    vec![
+        ("PI", Float(consts::PI)),
+        ("E", Float(consts::E)),
+        ("abs", func(abs)),
+        ("sqrt", func(sqrt)),
+        ("cbrt", func(fn_f64!("cbrt", f64::cbrt))),
+        ("pow", func(pow)),
+        ("exp", func(fn_f64!("exp", f64::exp))),
+        ("log", func(|a| log_call("log", &a, f64::ln))),
+        ("log10", func(|a| log_call("log10", &a, f64::log10))),
+        ("floor", func(fn_round!("floor", f64::floor, BigRational::floor))),
+        ("ceil", func(fn_round!("ceil", f64::ceil, BigRational::ceil))),
+        ("round", func(fn_round!("round", f64::round, BigRational::round))),
+        ("sin", func(fn_f64!("sin", f64::sin))),
+        ("cos", func(fn_f64!("cos", f64::cos))),
+        ("tan", func(fn_f64!("tan", f64::tan))),
+        ("asin", func(fn_f64!("asin", f64::asin))),
+        ("acos", func(fn_f64!("acos", f64::acos))),
+        ("atan", func(fn_f64!("atan", f64::atan))),
+        ("atan2", func(fn_f64_f64!("atan2", f64::atan2))),
+        ("sinh", func(fn_f64!("sinh", f64::sinh))),
+        ("cosh", func(fn_f64!("cosh", f64::cosh))),
+        ("tanh", func(fn_f64!("tanh", f64::tanh))),
+        ("hypot", func(fn_f64_f64!("hypot", f64::hypot))),
+        ("min", func(|a| extremum(a, "min", |n, best| n < best))),
+        ("max", func(|a| extremum(a, "max", |n, best| n > best))),
+        ("quot", func(quot)),
+        ("rem", func(rem)),
+        ("mod", func(modulo)),
+        ("unchecked-add", func(fn_unchecked!("unchecked-add", i64::wrapping_add, 2))),
+        ("unchecked-subtract", func(fn_unchecked!("unchecked-subtract", i64::wrapping_sub, 2))),
+        ("unchecked-multiply", func(fn_unchecked!("unchecked-multiply", i64::wrapping_mul, 2))),
+        ("unchecked-negate", func(fn_unchecked!("unchecked-negate", i64::wrapping_neg))),
+        ("unchecked-inc", func(fn_unchecked!("unchecked-inc", |n: i64| n.wrapping_add(1)))),
+        ("unchecked-dec", func(fn_unchecked!("unchecked-dec", |n: i64| n.wrapping_sub(1)))),
+        (
+            "unchecked-divide-int",
+            func(|a| unchecked_divide(a, "unchecked-divide-int", i64::wrapping_div)),
+        ),
+        (
+            "unchecked-remainder-int",
+            func(|a| unchecked_divide(a, "unchecked-remainder-int", i64::wrapping_rem)),
+        ),
+        ("bit-and", func(|a| bit_fold(a, "bit-and", |x, y| x & y))),
+        ("bit-or", func(|a| bit_fold(a, "bit-or", |x, y| x | y))),
+        ("bit-xor", func(|a| bit_fold(a, "bit-xor", |x, y| x ^ y))),
+        ("bit-not", func(bit_not)),
+        ("bit-shift-left", func(|a| bit_shift(a, "bit-shift-left", |x, y| x << y))),
+        ("bit-shift-right", func(|a| bit_shift(a, "bit-shift-right", |x, y| x >> y))),
+        ("unsigned-bit-shift-right", func(unsigned_bit_shift_right)),
+        ("bit-test", func(bit_test)),
+        ("bit-set", func(bit_set)),
+        ("bit-clear", func(bit_clear)),
+        ("bit-flip", func(bit_flip)),
+        ("bit-count", func(bit_count)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mod_rem_quot() {
+        assert!(matches!(modulo(vec![Int(-7), Int(3)]), Ok(Int(2))));
+        assert!(matches!(rem(vec![Int(-7), Int(3)]), Ok(Int(-1))));
+        assert!(matches!(quot(vec![Int(-7), Int(2)]), Ok(Int(-3))));
+        assert!(modulo(vec![Int(1), Int(0)]).is_err());
+    }
+
+    #[test]
+    fn test_pow_is_exact_for_integers() {
+        assert!(matches!(pow(vec![Int(2), Int(10)]), Ok(Int(1024))));
+        assert!(matches!(pow(vec![Int(2), Int(64)]), Ok(MalVal::BigInt(_))));
+        assert!(matches!(pow(vec![Int(2), Int(-1)]), Ok(MalVal::Ratio(_))));
+        assert!(pow(vec![Int(0), Int(-1)]).is_err());
+        assert!(pow(vec![Int(0), Float(-1.0)]).is_err());
+        assert!(pow(vec![Float(0.0), Int(-1)]).is_err());
+        assert!(matches!(pow(vec![Float(0.0), Float(2.0)]), Ok(Float(_))));
+        assert!(pow(vec![Int(2), Int(1_000_000_000_000)]).is_err());
+        assert!(matches!(pow(vec![Int(-1), Int(1_000_000_000_001)]), Ok(Int(-1))));
+    }
+
+    #[test]
+    fn test_unchecked_wraps() {
+        let add: fn(MalArgs) -> MalRet = fn_unchecked!("unchecked-add", i64::wrapping_add, 2);
+        assert!(matches!(add(vec![Int(i64::MAX), Int(1)]), Ok(Int(i64::MIN))));
+        assert!(add(vec![Float(1.0), Int(1)]).is_err());
+        let div = |a: MalArgs| unchecked_divide(a, "unchecked-divide-int", i64::wrapping_div);
+        assert!(matches!(div(vec![Int(i64::MIN), Int(-1)]), Ok(Int(i64::MIN))));
+        assert!(div(vec![Int(1), Int(0)]).is_err());
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert!(matches!(bit_not(vec![Int(5)]), Ok(Int(-6))));
+        assert!(matches!(bit_clear(vec![Int(7), Int(1)]), Ok(Int(5))));
+        assert!(matches!(bit_test(vec![Int(4), Int(2)]), Ok(Bool(true))));
+        assert!(matches!(bit_count(vec![Int(-1)]), Ok(Int(64))));
+        assert!(matches!(unsigned_bit_shift_right(vec![Int(-1), Int(60)]), Ok(Int(15))));
+        assert!(bit_fold(vec![Float(6.0), Int(3)], "bit-and", |x, y| x & y).is_err());
+        assert!(bit_shift(vec![Int(1), Int(-1)], "bit-shift-left", |x, y| x << y).is_err());
+        assert!(bit_shift(vec![Int(1), Int(100_000_000_000)], "bit-shift-left", |x, y| x << y).is_err());
+        assert!(bit_set(vec![Int(0), Int(MAX_BITS as i64 + 1)]).is_err());
+    }
+
+    #[test]
+    fn test_domain_errors() {
+        assert!(sqrt(vec![Int(-1)]).is_err());
+        assert!(log_call("log", &vec![Int(0)], f64::ln).is_err());
+        assert!(float_call("acos", &vec![Int(2)], |x| x[0].acos()).is_err());
+    }
+}
@@ -1,9 +1,34 @@
 use std::ops::{Add, Sub, Mul, Div, BitAnd, BitOr, BitXor, Neg, Rem, Shl, Shr, };
 use std::cmp::Ordering;
 
-#[derive(Debug, Clone, Copy)]
-enum Number {
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, ToPrimitive};
+
+use crate::types::MalErr::{ErrMalVal, ErrString};
+use crate::types::MalVal::Str;
+use crate::types::{hash_map, keyword, MalErr, MalVal};
+
+// Thrown as {:type :arithmetic :message msg} so that try* handlers can
+// tell arithmetic failures apart from other errors.
+pub fn arithmetic_error(msg: &str) -> MalErr {
+    let kvs = vec![
+        keyword("type"),
+        keyword("arithmetic"),
+        keyword("message"),
+        Str(msg.to_string()),
+    ];
+    match hash_map(kvs) {
+        Ok(hm) => ErrMalVal(hm),
+        Err(e) => e,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Number {
     Int(i64),
+    BigInt(BigInt),
+    Ratio(BigRational),
     Float(f64),
 }
 
@@ -19,42 +44,179 @@ impl From<f64> for Number {
     }
 }
 
+// Big integers are demoted back to Int whenever they fit.
+impl From<BigInt> for Number {
+    fn from(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(i) => Number::Int(i),
+            None => Number::BigInt(n),
+        }
+    }
+}
+
+// Ratios with a denominator of one collapse to integers.
+impl From<BigRational> for Number {
+    fn from(n: BigRational) -> Self {
+        if n.is_integer() {
+            Number::from(n.to_integer())
+        } else {
+            Number::Ratio(n)
+        }
+    }
+}
+
+impl TryFrom<&MalVal> for Number {
+    type Error = MalErr;
+
+    fn try_from(mv: &MalVal) -> Result<Self, Self::Error> {
+        match mv {
+            MalVal::Int(n) => Ok(Number::Int(*n)),
+            MalVal::BigInt(n) => Ok(Number::BigInt(n.clone())),
+            MalVal::Ratio(n) => Ok(Number::Ratio(n.clone())),
+            MalVal::Float(f) => Ok(Number::Float(*f)),
+            _ => Err(ErrString(format!("expecting number, got {}", mv.pr_str(true)))),
+        }
+    }
+}
+
+impl From<Number> for MalVal {
+    fn from(n: Number) -> Self {
+        match n {
+            Number::Int(n) => MalVal::Int(n),
+            Number::BigInt(n) => MalVal::BigInt(n),
+            Number::Ratio(n) => MalVal::Ratio(n),
+            Number::Float(f) => MalVal::Float(f),
+        }
+    }
+}
+
 impl Number {
-    fn as_f64(self) -> f64 {
+    pub fn as_f64(&self) -> f64 {
         match self {
-            Number::Int(n) => n as f64,
-            Number::Float(n) => n,
+            Number::Int(n) => *n as f64,
+            Number::BigInt(n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Ratio(n) => n.to_f64().unwrap_or(f64::NAN),
+            Number::Float(n) => *n,
         }
     }
+
+    // Ratios and floats are truncated towards zero.
+    pub fn to_bigint(&self) -> BigInt {
+        match self {
+            Number::Int(n) => BigInt::from(*n),
+            Number::BigInt(n) => n.clone(),
+            Number::Ratio(n) => n.to_integer(),
+            Number::Float(f) => BigInt::from_f64(f.trunc()).unwrap_or_default(),
+        }
+    }
+
+    // Floats convert exactly, so 0.1 becomes a ratio over a power of two.
+    pub fn to_ratio(&self) -> BigRational {
+        match self {
+            Number::Ratio(n) => n.clone(),
+            Number::Float(f) => BigRational::from_float(*f).unwrap_or_default(),
+            n => BigRational::from_integer(n.to_bigint()),
+        }
+    }
+
+    pub fn numerator(&self) -> Result<Number, MalErr> {
+        match self {
+            Number::Ratio(n) => Ok(Number::from(n.numer().clone())),
+            Number::Int(_) | Number::BigInt(_) => Ok(self.clone()),
+            Number::Float(_) => Err(ErrString("numerator: expecting rational".to_string())),
+        }
+    }
+
+    pub fn denominator(&self) -> Result<Number, MalErr> {
+        match self {
+            Number::Ratio(n) => Ok(Number::from(n.denom().clone())),
+            Number::Int(_) | Number::BigInt(_) => Ok(Number::Int(1)),
+            Number::Float(_) => Err(ErrString("denominator: expecting rational".to_string())),
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Number::Float(_))
+    }
+
+    // Exact division by zero is an error; float division follows IEEE 754.
+    pub fn checked_div(self, other: Number) -> Result<Number, MalErr> {
+        if !self.is_float() && other == Number::Int(0) && !other.is_float() {
+            return Err(arithmetic_error("divide by zero"));
+        }
+        Ok(self / other)
+    }
+
+    // Unlike to_ratio, floats go through their shortest decimal form so
+    // that (rationalize 0.1) is 1/10.
+    pub fn rationalize(&self) -> Result<Number, MalErr> {
+        let f = match self {
+            Number::Float(f) if f.is_finite() => *f,
+            Number::Float(_) => return Err(ErrString("rationalize: non-finite float".to_string())),
+            n => return Ok(n.clone()),
+        };
+        let s = format!("{}", f);
+        let (int, frac) = s.split_once('.').unwrap_or((&s, ""));
+        let numer: BigInt = format!("{}{}", int, frac).parse().unwrap_or_default();
+        let denom = num_traits::pow(BigInt::from(10), frac.len());
+        Ok(Number::from(BigRational::new(numer, denom)))
+    }
 }
 
 macro_rules! impl_op {
-    ($trait:ident, $method:ident) => {
+    ($trait:ident, $method:ident, $checked:ident) => {
         impl $trait for Number {
             type Output = Number;
 
             fn $method(self, other: Self) -> Self::Output {
                 match (self, other) {
-                    (Number::Int(a), Number::Int(b)) => Number::Int(a.$method(b)),
-                    (a, b) => Number::Float(a.as_f64().$method(b.as_f64())),
+                    (Number::Float(a), b) => Number::Float(a.$method(b.as_f64())),
+                    (a, Number::Float(b)) => Number::Float(a.as_f64().$method(b)),
+                    (Number::Int(a), Number::Int(b)) => match a.$checked(b) {
+                        Some(n) => Number::Int(n),
+                        None => Number::from(BigInt::from(a).$method(BigInt::from(b))),
+                    },
+                    (a @ Number::Ratio(_), b) | (a, b @ Number::Ratio(_)) => {
+                        Number::from(a.to_ratio().$method(b.to_ratio()))
+                    }
+                    (a, b) => Number::from(a.to_bigint().$method(b.to_bigint())),
                 }
             }
         }
     };
 }
 
-impl_op!(Add, add);
-impl_op!(Sub, sub);
-impl_op!(Mul, mul);
-impl_op!(Div, div);
-impl_op!(Rem, rem);
+impl_op!(Add, add, checked_add);
+impl_op!(Sub, sub, checked_sub);
+impl_op!(Mul, mul, checked_mul);
+impl_op!(Rem, rem, checked_rem);
+
+// Integer division is exact: it only stays an integer when it divides evenly.
+// Panics on exact division by zero, see checked_div.
+impl Div for Number {
+    type Output = Number;
+
+    fn div(self, other: Self) -> Self::Output {
+        match (self, other) {
+            (Number::Float(a), b) => Number::Float(a / b.as_f64()),
+            (a, Number::Float(b)) => Number::Float(a.as_f64() / b),
+            (Number::Int(a), Number::Int(b)) if a.checked_rem(b) == Some(0) => Number::Int(a / b),
+            (a, b) => Number::from(a.to_ratio() / b.to_ratio()),
+        }
+    }
+}
 
 impl Neg for Number {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
         match self {
-            Number::Int(n) => Number::Int(-n),
+            Number::Int(n) => match n.checked_neg() {
+                Some(n) => Number::Int(n),
+                None => Number::from(-BigInt::from(n)),
+            },
+            Number::BigInt(n) => Number::from(-n),
+            Number::Ratio(n) => Number::Ratio(-n),
             Number::Float(n) => Number::Float(-n),
         }
     }
@@ -66,17 +228,10 @@ macro_rules! impl_bitwise_op {
             type Output = Number;
             
             fn $method(self, rhs: Self) -> Self::Output {
-                let lhs = match self {
-                    Number::Int(n) => n,
-                    Number::Float(f) => f as i64, // Truncate float to integer
-                };
-
-                let rhs = match rhs {
-                    Number::Int(n) => n,
-                    Number::Float(f) => f as i64, // Truncate float to integer
-                };
-
-                Number::Int(lhs.$method(rhs)) // Perform bitwise operation
+                match (self, rhs) {
+                    (Number::Int(a), Number::Int(b)) => Number::Int(a.$method(b)),
+                    (a, b) => Number::from(a.to_bigint().$method(b.to_bigint())),
+                }
             }
         }
     };
@@ -85,18 +240,42 @@ macro_rules! impl_bitwise_op {
 impl_bitwise_op!(BitAnd, bitand);
 impl_bitwise_op!(BitOr, bitor);
 impl_bitwise_op!(BitXor, bitxor);
-impl_bitwise_op!(Shr, shr);
-impl_bitwise_op!(Shl, shl);
+
+// Shifts go through BigInt so that shifting left never overflows.
+macro_rules! impl_shift_op {
+    ($trait:ident, $method:ident) => {
+        impl $trait for Number {
+            type Output = Number;
+
+            fn $method(self, rhs: Self) -> Self::Output {
+                let n = rhs.to_bigint().to_usize().unwrap_or(0);
+                Number::from(self.to_bigint().$method(n))
+            }
+        }
+    };
+}
+
+impl_shift_op!(Shr, shr);
+impl_shift_op!(Shl, shl);
 
 impl PartialEq for Number {
     fn eq(&self, other: &Self) -> bool {
-        self.as_f64() == other.as_f64()
+        self.partial_cmp(other) == Some(Ordering::Equal)
     }
 }
 
 impl PartialOrd for Number {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.as_f64().partial_cmp(&other.as_f64())
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.partial_cmp(b),
+            (Number::Float(_), _) | (_, Number::Float(_)) => {
+                self.as_f64().partial_cmp(&other.as_f64())
+            }
+            (Number::Ratio(_), _) | (_, Number::Ratio(_)) => {
+                self.to_ratio().partial_cmp(&other.to_ratio())
+            }
+            (a, b) => a.to_bigint().partial_cmp(&b.to_bigint()),
+        }
     }
 }
 
@@ -124,7 +303,7 @@ mod tests {
 
     #[test]
     fn test_division() {
-        assert_eq!(Number::Int(10) / Number::Int(5), Number::Float(2.0)); // Always returns Float
+        assert!(matches!(Number::Int(10) / Number::Int(5), Number::Int(2)));
         assert_eq!(Number::Int(10) / Number::Float(2.5), Number::Float(4.0));
     }
 
@@ -157,4 +336,58 @@ mod tests {
         assert_eq!(Number::Int(1) << Number::Int(3), Number::Int(8)); // 1 << 3 = 8
         assert_eq!(Number::Float(6.0) & Number::Float(3.2), Number::Int(2));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_mal_conversion() {
+        assert!(matches!(Number::try_from(&MalVal::Int(3)), Ok(Number::Int(3))));
+        assert!(matches!(Number::try_from(&MalVal::Float(1.5)), Ok(Number::Float(f)) if f == 1.5));
+        assert!(Number::try_from(&MalVal::Nil).is_err());
+        assert!(matches!(MalVal::from(Number::Int(1) + Number::Float(0.5)), MalVal::Float(f) if f == 1.5));
+    }
+
+    #[test]
+    fn test_overflow_promotion() {
+        let big = Number::Int(i64::MAX) + Number::Int(1);
+        assert!(matches!(big, Number::BigInt(_)));
+        assert_eq!(big.to_bigint(), BigInt::from(i64::MAX) + 1);
+        assert!(matches!(Number::Int(i64::MIN) * Number::Int(-1), Number::BigInt(_)));
+        assert!(matches!(-Number::Int(i64::MIN), Number::BigInt(_)));
+        assert!(Number::Int(i64::MAX) + Number::Int(1) > Number::Int(i64::MAX));
+    }
+
+    #[test]
+    fn test_bigint_demotion() {
+        let big = Number::Int(i64::MAX) + Number::Int(1);
+        assert!(matches!(big.clone() - Number::Int(1), Number::Int(i64::MAX)));
+        assert!(matches!(big / Number::Int(2), Number::Int(_)));
+        assert!(matches!(Number::Int(1) << Number::Int(64), Number::BigInt(_)));
+    }
+    #[test]
+    fn test_ratios() {
+        let third = Number::Int(1) / Number::Int(3);
+        assert!(matches!(third, Number::Ratio(_)));
+        assert!(matches!(third.clone() * Number::Int(3), Number::Int(1)));
+        assert!(matches!(Number::Int(2) / Number::Int(4), Number::Ratio(ref r) if r.to_string() == "1/2"));
+        assert!(third < Number::Float(0.34));
+        assert!(third > Number::Int(0));
+        assert_eq!(third.clone() + Number::Float(1.0), Number::Float(1.0 + 1.0 / 3.0));
+        assert!(matches!(third.denominator(), Ok(Number::Int(3))));
+    }
+
+    #[test]
+    fn test_checked_division() {
+        assert!(Number::Int(1).checked_div(Number::Int(0)).is_err());
+        assert!((Number::Int(1) / Number::Int(3)).checked_div(Number::Int(0)).is_err());
+        assert!(matches!(Number::Float(1.0).checked_div(Number::Int(0)), Ok(Number::Float(f)) if f.is_infinite()));
+        assert!(matches!(Number::Int(1).checked_div(Number::Float(0.0)), Ok(Number::Float(f)) if f.is_infinite()));
+        assert!(matches!(arithmetic_error("x"), ErrMalVal(MalVal::Hash(_, _))));
+    }
+
+    #[test]
+    fn test_rationalize() {
+        let r = Number::Float(0.1).rationalize().ok().unwrap();
+        assert!(matches!(r, Number::Ratio(ref r) if r.to_string() == "1/10"));
+        assert!(matches!(Number::Float(2.0).rationalize(), Ok(Number::Int(2))));
+        assert!(Number::Float(f64::NAN).rationalize().is_err());
+    }
+}
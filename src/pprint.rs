@@ -0,0 +1,311 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::printer::{PrintLimits, CYCLE};
+use crate::types::MalVal::{Atom, Hash, Int, List, MalFunc, Nil, Set, Str, Sym, Vector};
+use crate::types::MalErr::ErrString;
+use crate::types::{error, func, MalArgs, MalErr, MalRet, MalVal};
+
+// Right margin used when none is given.
+pub const DEFAULT_WIDTH: usize = 80;
+
+// A layout in the style of Wadler's "A prettier printer". Every Line in a
+// Group is printed as a space if the whole group fits on the current
+// line, and as a newline plus the current indentation otherwise. Nest
+// adds to the indentation; Align sets it to the current column.
+enum Doc {
+    Text(String),
+    Line,
+    Nest(usize, Box<Doc>),
+    Align(Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+use Doc::{Align, Concat, Group, Line, Nest, Text};
+
+fn text(s: &str) -> Doc {
+    Text(s.to_string())
+}
+
+// docs separated by Lines.
+fn lines(docs: Vec<Doc>) -> Vec<Doc> {
+    let mut res = vec![];
+    for (i, d) in docs.into_iter().enumerate() {
+        if i > 0 {
+            res.push(Line);
+        }
+        res.push(d);
+    }
+    res
+}
+
+// open, the items aligned one column past it, then close.
+fn seq(open: &str, items: Vec<Doc>, close: &str) -> Doc {
+    let width = open.chars().count();
+    Group(Box::new(Align(Box::new(Concat(vec![
+        text(open),
+        Nest(width, Box::new(Concat(lines(items)))),
+        text(close),
+    ])))))
+}
+
+// How many arguments stay on the first line of a special form, with the
+// rest indented two columns as a body.
+fn header_args(head: &MalVal) -> Option<usize> {
+    match head {
+        Sym(s) => match s.as_str() {
+            "def!" | "defmacro!" | "let*" | "fn*" | "if" | "catch*" => Some(1),
+            "do" | "try*" => Some(0),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Builds the Doc for a value, applying the same print limits and atom
+// cycle check as pr_str.
+struct Layout {
+    limits: PrintLimits,
+    atoms: Vec<*const RefCell<MalVal>>,
+}
+
+impl Layout {
+    // Docs for the first *print-length* items, then "..." if any were cut.
+    fn items<'a>(
+        &mut self,
+        items: impl ExactSizeIterator<Item = &'a MalVal>,
+        depth: usize,
+    ) -> Vec<Doc> {
+        let len = items.len();
+        let mut docs: Vec<Doc> =
+            items.take(self.limits.take()).map(|v| self.doc(v, depth + 1)).collect();
+        if len > self.limits.take() {
+            docs.push(text("..."));
+        }
+        docs
+    }
+
+    fn body_form(&mut self, l: &[MalVal], n: usize, depth: usize) -> Doc {
+        let n = n.min(l.len() - 1);
+        let mut first = vec![text("("), self.doc(&l[0], depth + 1)];
+        for v in &l[1..=n] {
+            first.push(text(" "));
+            first.push(match (&l[0], v) {
+                (Sym(s), Vector(b, _)) if s == "let*" && !self.limits.too_deep(depth + 1) => {
+                    self.bindings(b, depth + 1)
+                }
+                _ => self.doc(v, depth + 1),
+            });
+        }
+        let body: Vec<Doc> =
+            l[n + 1..].iter().flat_map(|v| [Line, self.doc(v, depth + 1)]).collect();
+        first.push(Nest(2, Box::new(Concat(body))));
+        first.push(text(")"));
+        Group(Box::new(Align(Box::new(Concat(first)))))
+    }
+
+    // Calls keep the first argument next to the name and line the rest up
+    // under it.
+    fn call(&mut self, l: &[MalVal], depth: usize) -> Doc {
+        let head = self.doc(&l[0], depth + 1);
+        let args = self.items(l[1..].iter(), depth);
+        Group(Box::new(Concat(vec![
+            text("("),
+            head,
+            text(" "),
+            Align(Box::new(Concat(lines(args)))),
+            text(")"),
+        ])))
+    }
+
+    // Binding vectors keep each name next to its value.
+    fn bindings(&mut self, v: &[MalVal], depth: usize) -> Doc {
+        let pairs = v
+            .chunks(2)
+            .map(|p| {
+                let docs = p.iter().map(|v| self.doc(v, depth + 1)).collect();
+                Group(Box::new(Concat(lines(docs))))
+            })
+            .collect();
+        seq("[", pairs, "]")
+    }
+
+    fn doc(&mut self, v: &MalVal, depth: usize) -> Doc {
+        match v {
+            List(..) | Vector(..) | Set(..) | Hash(..) if self.limits.too_deep(depth) => text("#"),
+            List(l, _) if !l.is_empty() && l.len() <= self.limits.take() => {
+                match (header_args(&l[0]), &l[0]) {
+                    (Some(n), _) => self.body_form(l, n, depth),
+                    (None, Sym(_)) if l.len() > 1 => self.call(l, depth),
+                    _ => seq("(", self.items(l.iter(), depth), ")"),
+                }
+            }
+            List(l, _) => seq("(", self.items(l.iter(), depth), ")"),
+            Vector(l, _) => seq("[", self.items(l.iter(), depth), "]"),
+            Set(l, _) => seq("#{", self.items(l.iter(), depth), "}"),
+            Hash(hm, _) => {
+                let mut entries: Vec<Doc> = hm
+                    .iter()
+                    .take(self.limits.take())
+                    .map(|(k, v)| {
+                        Group(Box::new(Concat(vec![
+                            self.doc(k, depth + 1),
+                            Nest(2, Box::new(Concat(vec![Line, self.doc(v, depth + 1)]))),
+                        ])))
+                    })
+                    .collect();
+                if hm.len() > self.limits.take() {
+                    entries.push(text("..."));
+                }
+                seq("{", entries, "}")
+            }
+            MalFunc { ast, params, .. } => self.body_form(
+                &[Sym("fn*".to_string()), (**params).clone(), (**ast).clone()],
+                1,
+                depth,
+            ),
+            Atom(a) if self.atoms.contains(&Rc::as_ptr(a)) => text(CYCLE),
+            Atom(a) => {
+                self.atoms.push(Rc::as_ptr(a));
+                let inner = self.doc(&a.borrow(), depth);
+                self.atoms.pop();
+                seq("(atom ", vec![inner], ")")
+            }
+            _ => Text(v.pr_str(true)),
+        }
+    }
+}
+
+// Whether the text up to the next line break fits in width columns,
+// given the group being tried flat followed by the rest of the stack.
+fn fits(mut width: isize, first: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut stack = vec![(0, true, first)];
+    let mut rest = rest.iter().rev();
+    loop {
+        if width < 0 {
+            return false;
+        }
+        let (i, flat, d) = match stack.pop().or_else(|| rest.next().copied()) {
+            Some(item) => item,
+            None => return true,
+        };
+        match d {
+            Text(s) => width -= s.chars().count() as isize,
+            Line if flat => width -= 1,
+            Line => return true,
+            Nest(j, d) => stack.push((i + j, flat, d)),
+            Align(d) | Group(d) => stack.push((i, flat, d)),
+            Concat(ds) => stack.extend(ds.iter().rev().map(|d| (i, flat, d))),
+        }
+    }
+}
+
+fn render(d: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    let mut stack = vec![(0, false, d)];
+    while let Some((i, flat, d)) = stack.pop() {
+        match d {
+            Text(s) => {
+                out.push_str(s);
+                col += s.chars().count();
+            }
+            Line if flat => {
+                out.push(' ');
+                col += 1;
+            }
+            Line => {
+                out.push('\n');
+                out.push_str(&" ".repeat(i));
+                col = i;
+            }
+            Nest(j, d) => stack.push((i + j, flat, d)),
+            Align(d) => stack.push((col, flat, d)),
+            Concat(ds) => stack.extend(ds.iter().rev().map(|d| (i, flat, d))),
+            Group(d) => {
+                let flat = flat || fits(width as isize - col as isize, d, &stack);
+                stack.push((i, flat, d));
+            }
+        }
+    }
+    out
+}
+
+// v printed readably, broken across lines to stay within width columns
+// where possible.
+pub fn pprint_str(v: &MalVal, width: usize) -> String {
+    let mut layout = Layout {
+        limits: PrintLimits::current(),
+        atoms: vec![],
+    };
+    render(&layout.doc(v, 0), width)
+}
+
+// The optional second argument of pprint and pprint-str.
+fn width_arg(a: &MalArgs) -> Result<usize, MalErr> {
+    match a.get(1) {
+        None | Some(Nil) => Ok(DEFAULT_WIDTH),
+        Some(Int(n)) if *n > 0 => Ok(*n as usize),
+        _ => Err(ErrString("pprint: expecting positive integer width".to_string())),
+    }
+}
+
+pub fn ns() -> Vec<(&'static str, MalVal)> {
+    vec![
+        (
+            "pprint-str",
+            func(|a| match a.first() {
+                Some(v) => Ok(Str(pprint_str(v, width_arg(&a)?))),
+                None => error("pprint-str: expecting a value"),
+            }),
+        ),
+        (
+            "pprint",
+            func(|a| -> MalRet {
+                match a.first() {
+                    Some(v) => println!("{}", pprint_str(v, width_arg(&a)?)),
+                    None => return error("pprint: expecting a value"),
+                }
+                Ok(Nil)
+            }),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::read_str;
+
+    fn pp(s: &str, width: usize) -> String {
+        pprint_str(&read_str(s).ok().unwrap(), width)
+    }
+
+    #[test]
+    fn test_fits_on_one_line() {
+        assert_eq!(pp("(a [b c] {:d 1})", 80), "(a [b c] {:d 1})");
+        assert_eq!(pp("(let* [x 1] x)", 80), "(let* [x 1] x)");
+    }
+
+    #[test]
+    fn test_breaks() {
+        assert_eq!(pp("(foo bar baz)", 10), "(foo bar\n     baz)");
+        assert_eq!(pp("(1 2 3)", 4), "(1\n 2\n 3)");
+        assert_eq!(pp("[[1 2] [3 4]]", 8), "[[1 2]\n [3 4]]");
+        assert_eq!(pp("#{aaaa bbbb}", 8), "#{aaaa\n  bbbb}");
+    }
+
+    #[test]
+    fn test_special_forms() {
+        assert_eq!(
+            pp("(def! f (fn* (x) (+ x 1)))", 16),
+            "(def! f\n  (fn* (x)\n    (+ x 1)))"
+        );
+        assert_eq!(
+            pp("(let* [a 1 b 2] (+ a b))", 14),
+            "(let* [a 1\n       b 2]\n  (+ a b))"
+        );
+        assert_eq!(pp("(do (a) (b))", 8), "(do\n  (a)\n  (b))");
+    }
+}
@@ -1,51 +1,220 @@
-use crate::types::MalVal;
-use crate::types::MalVal::{Atom, Bool, Func, Hash, Int, Float, List, MalFunc, Nil, Str, Sym, Vector};
+use std::cell::RefCell;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
 
+use num_bigint::BigInt;
+
+use crate::env::{env_get, Env};
+use crate::number::Number;
+use crate::types::MalErr::ErrString;
+use crate::types::{MalErr, MalVal};
+use crate::types::MalVal::{
+    Atom, Bool, Char, Float, Func, Hash, Int, List, MalFunc, Nil, Ratio, Set, Str, Sym,
+    Vector,
+};
+
+// The reader's names for characters that cannot be written after a backslash.
+pub const CHAR_NAMES: [(&str, char); 6] = [
+    ("newline", '\n'),
+    ("space", ' '),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("backspace", '\u{8}'),
+    ("formfeed", '\u{c}'),
+];
+
+fn char_literal(c: char) -> String {
+    match CHAR_NAMES.iter().find(|(_, ch)| *ch == c) {
+        Some((name, _)) => format!("\\{}", name),
+        // The reader ends a character token at whitespace, so unnamed
+        // whitespace such as U+00A0 needs a code point as well.
+        None if c.is_control() || c.is_whitespace() => format!("\\u{:04x}", c as u32),
+        None => format!("\\{}", c),
+    }
+}
+
+// The inverse of the reader's unescape_str.
 fn escape_str(s: &str) -> String {
     s.chars()
         .map(|c| match c {
             '"' => "\\\"".to_string(),
             '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\r' => "\\r".to_string(),
+            '\0' => "\\0".to_string(),
+            '\u{1b}' => "\\e".to_string(),
             '\\' => "\\\\".to_string(),
+            _ if c.is_control() => format!("\\u{:04x}", c as u32),
             _ => c.to_string(),
         })
         .collect::<Vec<String>>()
         .join("")
 }
 
-impl MalVal {
-    pub fn pr_str(&self, print_readably: bool) -> String {
-        match self {
+// Shortest digits that read back as the same f64, always with a '.' or
+// exponent so the reader does not take it for an integer.
+fn float_str(f: f64) -> String {
+    match f {
+        _ if f.is_nan() => "##NaN".to_string(),
+        f64::INFINITY => "##Inf".to_string(),
+        f64::NEG_INFINITY => "##-Inf".to_string(),
+        _ => format!("{:?}", f),
+    }
+}
+
+thread_local! {
+    // Where *print-length* and *print-level* are looked up. This is the
+    // top-level environment, not the one printing is called from, so only
+    // values set with def! count; a let* binding of either has no effect.
+    static PRINT_ENV: RefCell<Option<Env>> = const { RefCell::new(None) };
+}
+
+pub fn set_print_env(env: &Env) {
+    PRINT_ENV.with_borrow_mut(|e| *e = Some(env.clone()));
+}
+
+// Collections past *print-length* items end in "..." and those nested
+// deeper than *print-level* print as "#". Unset or non-integer values
+// mean no limit.
+#[derive(Clone, Copy, Default)]
+pub struct PrintLimits {
+    pub length: Option<usize>,
+    pub level: Option<usize>,
+}
+
+impl PrintLimits {
+    pub fn current() -> PrintLimits {
+        PRINT_ENV.with_borrow(|env| {
+            let limit = |name| match env.as_ref().and_then(|e| env_get(e, name)) {
+                Some(Int(n)) if n >= 0 => Some(n as usize),
+                _ => None,
+            };
+            PrintLimits {
+                length: limit("*print-length*"),
+                level: limit("*print-level*"),
+            }
+        })
+    }
+
+    // Whether a collection at this depth prints as "#".
+    pub fn too_deep(&self, depth: usize) -> bool {
+        self.level.is_some_and(|l| depth >= l)
+    }
+
+    // How many items of a collection to print.
+    pub fn take(&self) -> usize {
+        self.length.unwrap_or(usize::MAX)
+    }
+}
+
+// Printed in place of an atom found inside its own value.
+pub const CYCLE: &str = "#<cycle>";
+
+struct Printer {
+    readably: bool,
+    limits: PrintLimits,
+    // Atoms being printed, outermost first, to break reference cycles.
+    atoms: Vec<*const RefCell<MalVal>>,
+}
+
+impl Printer {
+    fn print(&mut self, v: &MalVal, depth: usize) -> String {
+        match v {
             Nil => String::from("nil"),
             Bool(true) => String::from("true"),
             Bool(false) => String::from("false"),
             Int(i) => format!("{}", i),
-            Float(f)    => format!("{}", f),
+            MalVal::BigInt(i) => i.to_string(),
+            Ratio(r) => r.to_string(),
+            Float(f) => float_str(*f),
+            Char(c) if self.readably => char_literal(*c),
+            Char(c) => c.to_string(),
             Str(s) => {
                 if let Some(keyword) = s.strip_prefix('\u{29e}') {
                     format!(":{}", keyword)
-                } else if print_readably {
+                } else if self.readably {
                     format!("\"{}\"", escape_str(s))
                 } else {
                     s.clone()
                 }
             }
             Sym(s) => s.clone(),
-            List(l, _) => pr_seq(l, print_readably, "(", ")", " "),
-            Vector(l, _) => pr_seq(l, print_readably, "[", "]", " "),
+            List(l, _) => self.seq(l.iter(), depth, "(", ")"),
+            Vector(l, _) => self.seq(l.iter(), depth, "[", "]"),
+            Set(l, _) => self.seq(l.iter(), depth, "#{", "}"),
             Hash(hm, _) => {
-                let l: Vec<MalVal> = hm
+                if self.limits.too_deep(depth) {
+                    return "#".to_string();
+                }
+                let mut strs: Vec<String> = hm
                     .iter()
-                    .flat_map(|(k, v)| vec![Str(k.to_string()), v.clone()])
+                    .take(self.limits.take())
+                    .map(|(k, v)| {
+                        let k = self.print(k, depth + 1);
+                        format!("{} {}", k, self.print(v, depth + 1))
+                    })
                     .collect();
-                pr_seq(&l, print_readably, "{", "}", " ")
+                if hm.len() > self.limits.take() {
+                    strs.push("...".to_string());
+                }
+                format!("{{{}}}", strs.join(" "))
             }
             Func(_, _) => String::from("#<builtin>"),
             MalFunc {
                 ast: a, params: p, ..
-            } => format!("(fn* {} {})", p.pr_str(true), a.pr_str(true)),
-            Atom(a) => format!("(atom {})", a.borrow().pr_str(true)),
+            } => {
+                let p = self.readable(p, depth);
+                format!("(fn* {} {})", p, self.readable(a, depth))
+            }
+            Atom(a) => {
+                if self.atoms.contains(&Rc::as_ptr(a)) {
+                    return CYCLE.to_string();
+                }
+                self.atoms.push(Rc::as_ptr(a));
+                let inner = self.readable(&a.borrow(), depth);
+                self.atoms.pop();
+                format!("(atom {})", inner)
+            }
+        }
+    }
+
+    // Function bodies and atom contents always print readably.
+    fn readable(&mut self, v: &MalVal, depth: usize) -> String {
+        let readably = std::mem::replace(&mut self.readably, true);
+        let s = self.print(v, depth);
+        self.readably = readably;
+        s
+    }
+
+    fn seq<'a>(
+        &mut self,
+        items: impl ExactSizeIterator<Item = &'a MalVal>,
+        depth: usize,
+        start: &str,
+        end: &str,
+    ) -> String {
+        if self.limits.too_deep(depth) {
+            return "#".to_string();
         }
+        let len = items.len();
+        let mut strs: Vec<String> =
+            items.take(self.limits.take()).map(|x| self.print(x, depth + 1)).collect();
+        if len > self.limits.take() {
+            strs.push("...".to_string());
+        }
+        format!("{}{}{}", start, strs.join(" "), end)
+    }
+}
+
+impl MalVal {
+    pub fn pr_str(&self, print_readably: bool) -> String {
+        let mut printer = Printer {
+            readably: print_readably,
+            limits: PrintLimits::current(),
+            atoms: vec![],
+        };
+        printer.print(self, 0)
     }
 }
 
@@ -53,3 +222,204 @@ pub fn pr_seq(seq: &[MalVal], print_readably: bool, start: &str, end: &str, join
     let strs: Vec<String> = seq.iter().map(|x| x.pr_str(print_readably)).collect();
     format!("{}{}{}", start, strs.join(join), end)
 }
+
+// One %[flags][width][.precision]conversion in a format string.
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, body: String, numeric: bool) -> String {
+        let body = match numeric && self.plus && !body.starts_with('-') {
+            true => format!("+{}", body),
+            false => body,
+        };
+        let fill = self.width.saturating_sub(body.chars().count());
+        if self.left {
+            format!("{}{}", body, " ".repeat(fill))
+        } else if self.zero && numeric {
+            let sign = if body.starts_with(['-', '+']) { 1 } else { 0 };
+            format!("{}{}{}", &body[..sign], "0".repeat(fill), &body[sign..])
+        } else {
+            format!("{}{}", " ".repeat(fill), body)
+        }
+    }
+}
+
+// The largest width or precision format accepts. Rust's formatting
+// panics on precisions above u16::MAX, and a huge width would try to
+// allocate that much padding.
+const MAX_FORMAT_SIZE: usize = 10_000;
+
+// A width or precision; what names it in the error for one too large.
+fn digits(chars: &mut Peekable<Chars>, what: &str) -> Result<Option<usize>, MalErr> {
+    let mut n = None;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        let next = n.unwrap_or(0usize).checked_mul(10).and_then(|n| n.checked_add(d as usize));
+        match next {
+            Some(v) if v <= MAX_FORMAT_SIZE => n = Some(v),
+            _ => return Err(ErrString(format!("format: {} above {}", what, MAX_FORMAT_SIZE))),
+        }
+        chars.next();
+    }
+    Ok(n)
+}
+
+fn format_int(arg: &MalVal, radix: u32) -> Result<String, MalErr> {
+    match Number::try_from(arg)? {
+        Number::Int(n) => Ok(BigInt::from(n).to_str_radix(radix)),
+        Number::BigInt(n) => Ok(n.to_str_radix(radix)),
+        _ => Err(ErrString(format!("format: expecting integer, got {}", arg.pr_str(true)))),
+    }
+}
+
+// C-style exponent: 1.500000e+03 rather than Rust's 1.500000e3.
+fn format_exp(f: f64, precision: usize) -> String {
+    let s = format!("{:.*e}", precision, f);
+    match s.split_once('e') {
+        Some((m, e)) => {
+            let (sign, digits) = e.strip_prefix('-').map_or(("+", e), |d| ("-", d));
+            format!("{}e{}{:0>2}", m, sign, digits)
+        }
+        None => s,
+    }
+}
+
+// printf-style formatting with the conversions %s %d %f %e %x %X and %%.
+// Floats default to six digits after the point; %s uses str's printing.
+pub fn format(fmt: &str, args: &[MalVal]) -> Result<String, MalErr> {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = fmt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let mut spec = Spec::default();
+        while let Some(&f) = chars.peek() {
+            match f {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = digits(&mut chars, "width")?.unwrap_or(0);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(digits(&mut chars, "precision")?.unwrap_or(0));
+        }
+        let conv = match chars.next() {
+            Some(c) => c,
+            None => return Err(ErrString("format: incomplete format specifier".to_string())),
+        };
+        if conv == '%' {
+            out.push('%');
+            continue;
+        }
+        let arg = match args.next() {
+            Some(arg) => arg,
+            None => return Err(ErrString(format!("format: missing argument for %{}", conv))),
+        };
+        let precision = spec.precision.unwrap_or(6);
+        let (body, numeric) = match conv {
+            's' => {
+                let s = arg.pr_str(false);
+                match spec.precision {
+                    Some(p) => (s.chars().take(p).collect(), false),
+                    None => (s, false),
+                }
+            }
+            'd' => (format_int(arg, 10)?, true),
+            'x' => (format_int(arg, 16)?, true),
+            'X' => (format_int(arg, 16)?.to_uppercase(), true),
+            'f' => (format!("{:.*}", precision, Number::try_from(arg)?.as_f64()), true),
+            'e' => (format_exp(Number::try_from(arg)?.as_f64(), precision), true),
+            _ => return Err(ErrString(format!("format: unknown conversion %{}", conv))),
+        };
+        out.push_str(&spec.pad(body, numeric));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{env_new, env_sets};
+    use crate::reader::read_str;
+    use crate::types::atom;
+
+    #[test]
+    fn test_atom_cycle() {
+        let a = atom(&Int(1));
+        if let Atom(ref cell) = a {
+            *cell.borrow_mut() = vector![Int(1), a.clone()];
+        }
+        assert_eq!(a.pr_str(true), "(atom [1 #<cycle>])");
+    }
+
+    #[test]
+    fn test_print_limits() {
+        let env = env_new(None);
+        set_print_env(&env);
+        let v = vector![Int(1), vector![Int(2), vector!(vec![Int(3)])], Int(4)];
+        assert_eq!(v.pr_str(true), "[1 [2 [3]] 4]");
+        env_sets(&env, "*print-length*", Int(2));
+        env_sets(&env, "*print-level*", Int(2));
+        assert_eq!(v.pr_str(true), "[1 [2 #] ...]");
+        env_sets(&env, "*print-level*", Int(0));
+        assert_eq!(v.pr_str(true), "#");
+    }
+
+    #[test]
+    fn test_print_limits_ignore_local_bindings() {
+        let env = env_new(None);
+        set_print_env(&env);
+        let local = env_new(Some(env.clone()));
+        env_sets(&local, "*print-length*", Int(1));
+        let v = vector![Int(1), Int(2)];
+        assert_eq!(v.pr_str(true), "[1 2]");
+        env_sets(&env, "*print-length*", Int(1));
+        assert_eq!(v.pr_str(true), "[1 ...]");
+    }
+
+    #[test]
+    fn test_float_round_trip() {
+        for f in [1.0, -0.0, 0.1, 1.5e300, 1e-7, 123456789.125, f64::MAX, f64::MIN_POSITIVE] {
+            let s = Float(f).pr_str(true);
+            let back = read_str(&s);
+            assert!(matches!(back, Ok(Float(g)) if g == f && g.signum() == f.signum()), "{}", s);
+        }
+        assert_eq!(Float(1.0).pr_str(true), "1.0");
+        assert_eq!(Float(f64::INFINITY).pr_str(true), "##Inf");
+        assert_eq!(Float(f64::NEG_INFINITY).pr_str(true), "##-Inf");
+        assert_eq!(Float(f64::NAN).pr_str(true), "##NaN");
+        assert!(matches!(read_str("##NaN"), Ok(Float(f)) if f.is_nan()));
+    }
+
+    #[test]
+    fn test_format() {
+        let f = |fmt: &str, args: Vec<MalVal>| format(fmt, &args).ok().unwrap();
+        assert_eq!(f("%.2f", vec![Float(1.23456)]), "1.23");
+        assert_eq!(f("%f|%e", vec![Int(1), Float(1500.0)]), "1.000000|1.500000e+03");
+        let ints = vec![Int(42), Int(42), Int(-42), Int(7)];
+        assert_eq!(f("%5d|%-5d|%05d|%+d", ints), "   42|42   |-0042|+7");
+        assert_eq!(f("%x %X %d", vec![Int(255), Int(255), Int(-3)]), "ff FF -3");
+        let strs = vec![Str("a".to_string()), Str("abcdef".to_string())];
+        assert_eq!(f("%s %.3s 100%%", strs), "a abc 100%");
+        assert!(format("%d", &[]).is_err());
+        assert!(format("%d", &[Float(1.5)]).is_err());
+        assert!(format("%q", &[Int(1)]).is_err());
+        for big in ["%99999999999999999999999d", "%99999999999d", "%.70000f", "%.70000e"] {
+            assert!(format(big, &[Int(1)]).is_err(), "{}", big);
+        }
+        assert_eq!(f("%.10000f", vec![Int(1)]).len(), 10002);
+    }
+}
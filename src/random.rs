@@ -0,0 +1,201 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::number::Number;
+use crate::types::MalErr::ErrString;
+use crate::types::MalVal::{Float, Int, List, Nil, Set, Vector};
+use crate::types::{error, func, MalArgs, MalErr, MalRet, MalVal};
+
+// Read once, when the generator is first used.
+const SEED_VAR: &str = "MAL_RANDOM_SEED";
+
+// SplitMix64: tiny, fast and good enough for simulations. Not suitable
+// for anything cryptographic.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    // A bad seed is an error rather than a silent switch to a time-based
+    // seed, which would make a run that asked to be reproducible not be.
+    fn from_env() -> Result<Rng, MalErr> {
+        if let Some(s) = std::env::var_os(SEED_VAR) {
+            return match s.to_str().and_then(parse_seed) {
+                Some(seed) => Ok(Rng::new(seed)),
+                None => Err(ErrString(format!("{}: invalid seed {:?}", SEED_VAR, s))),
+            };
+        }
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Ok(Rng::new(nanos ^ (std::process::id() as u64).rotate_left(32)))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    // Uniform in [0, n), rejecting the biased tail of the u64 range.
+    fn below(&mut self, n: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+// Seeds are read as set-random-seed! takes them: a 64-bit integer,
+// negative values included. Values above i64::MAX are accepted too.
+fn parse_seed(s: &str) -> Option<u64> {
+    let s = s.trim();
+    s.parse::<i64>().map(|n| n as u64).or_else(|_| s.parse::<u64>()).ok()
+}
+
+lazy_static! {
+    // Created from the environment when first used.
+    static ref RNG: Mutex<Option<Rng>> = Mutex::new(None);
+}
+
+fn with_rng<T>(f: impl FnOnce(&mut Rng) -> T) -> Result<T, MalErr> {
+    let mut rng = RNG.lock().unwrap();
+    if rng.is_none() {
+        *rng = Some(Rng::from_env()?);
+    }
+    Ok(f(rng.as_mut().unwrap()))
+}
+
+fn seq_items<'a>(name: &str, mv: &'a MalVal) -> Result<Cow<'a, [MalVal]>, MalErr> {
+    match mv {
+        List(v, _) | Vector(v, _) => Ok(Cow::Borrowed(v)),
+        Set(v, _) => Ok(Cow::Owned(v.iter().cloned().collect())),
+        Nil => Ok(Cow::Borrowed(&[])),
+        _ => Err(ErrString(format!("{}: expecting sequence", name))),
+    }
+}
+
+fn set_random_seed(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(n) => {
+            *RNG.lock().unwrap() = Some(Rng::new(n as u64));
+            Ok(Nil)
+        }
+        _ => error("set-random-seed!: expecting integer seed"),
+    }
+}
+
+fn rand(a: MalArgs) -> MalRet {
+    let scale = match a.first() {
+        Some(n) => Number::try_from(n)?.as_f64(),
+        None => 1.0,
+    };
+    Ok(Float(with_rng(|rng| rng.next_f64())? * scale))
+}
+
+fn rand_int(a: MalArgs) -> MalRet {
+    match a[0] {
+        Int(n) if n > 0 => Ok(Int(with_rng(|rng| rng.below(n as u64))? as i64)),
+        _ => error("rand-int: expecting positive integer bound"),
+    }
+}
+
+fn rand_nth(a: MalArgs) -> MalRet {
+    let items = seq_items("rand-nth", &a[0])?;
+    if items.is_empty() {
+        return error("rand-nth: empty sequence");
+    }
+    let i = with_rng(|rng| rng.below(items.len() as u64))? as usize;
+    Ok(items[i].clone())
+}
+
+// Fisher-Yates.
+fn shuffle(a: MalArgs) -> MalRet {
+    let mut items = seq_items("shuffle", &a[0])?.into_owned();
+    with_rng(|rng| {
+        for i in (1..items.len()).rev() {
+            items.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+    })?;
+    Ok(vector!(items))
+}
+
+fn random_sample(a: MalArgs) -> MalRet {
+    let prob = Number::try_from(&a[0])?.as_f64();
+    let items = seq_items("random-sample", &a[1])?;
+    let kept = with_rng(|rng| {
+        items
+            .iter()
+            .filter(|_| rng.next_f64() < prob)
+            .cloned()
+            .collect()
+    })?;
+    Ok(list!(kept))
+}
+
+pub fn ns() -> Vec<(&'static str, MalVal)> {
+    vec![
+        ("set-random-seed!", func(set_random_seed)),
+        ("rand", func(rand)),
+        ("rand-int", func(rand_int)),
+        ("rand-nth", func(rand_nth)),
+        ("shuffle", func(shuffle)),
+        ("random-sample", func(random_sample)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequence_is_reproducible() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn test_parse_seed() {
+        assert_eq!(parse_seed(" 42 "), Some(42));
+        assert_eq!(parse_seed("-1"), Some(u64::MAX));
+        assert_eq!(parse_seed("18446744073709551615"), Some(u64::MAX));
+        assert_eq!(parse_seed("42x"), None);
+        assert_eq!(parse_seed(""), None);
+    }
+
+    #[test]
+    fn test_seq_items() {
+        let set = crate::types::hash_set(vec![Int(1), Int(2)]);
+        assert_eq!(seq_items("shuffle", &set).ok().unwrap().len(), 2);
+        assert!(seq_items("shuffle", &Int(1)).is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let f = rng.next_f64();
+            assert!((0.0..1.0).contains(&f));
+            assert!(rng.below(3) < 3);
+        }
+    }
+}
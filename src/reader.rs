@@ -1,69 +1,279 @@
-use regex::{Captures, Regex};
+use num_bigint::BigInt as Big;
+use num_rational::BigRational;
+use num_traits::Zero;
+use regex::Regex;
+use std::ops::Range;
 use std::rc::Rc;
 
+use crate::number::Number;
+use crate::printer::CHAR_NAMES;
+
 use crate::types::MalErr::ErrString;
-use crate::types::MalVal::{Bool, Int, Float, List, Nil, Str, Sym, Vector};
-use crate::types::{error, hash_map, MalErr, MalRet, MalVal};
+use crate::types::MalVal::{Bool, Char, Float, Hash, Int, List, Nil, Set, Str, Sym, Vector};
+use crate::types::{error, hash_map, hash_set, keyword, MalErr, MalMap, MalRet, MalVal};
+
+#[derive(Debug, Clone)]
+struct Token {
+  text: String,
+  line: usize,
+  col: usize,
+}
 
 #[derive(Debug, Clone)]
 struct Reader {
-  tokens: Vec<String>,
-  pos: usize
+  tokens: Vec<Token>,
+  pos: usize,
+  file: Option<Rc<str>>,
+  in_anon_fn: bool,
+  // Set when reading ran out of input rather than hitting bad syntax.
+  eof: bool,
 }
 
 impl Reader {
+  fn new(str: &str, file: Option<&str>) -> Reader {
+    Reader {
+      tokens: tokenize(str),
+      pos: 0,
+      file: file.map(Rc::from),
+      in_anon_fn: false,
+      eof: false,
+    }
+  }
+
   fn next(&mut self) -> Result<String, MalErr> {
+    let token = self.peek()?;
     self.pos += 1;
-    Ok(self
-      .tokens
-      .get(self.pos - 1)
-      .ok_or_else(|| ErrString("underflow".to_string()))?
-      .to_string()
-    )
+    Ok(token)
   }
 
-  fn peek(&self) -> Result<String, MalErr> {
-    Ok(self
-      .tokens
-      .get(self.pos)
-      .ok_or_else(|| ErrString("underflow".to_string()))?
-      .to_string()
-    )
+  fn peek(&mut self) -> Result<String, MalErr> {
+    match self.tokens.get(self.pos) {
+      Some(t) => Ok(t.text.to_string()),
+      None => {
+        self.eof = true;
+        Err(self.error_at(self.pos, "unexpected EOF"))
+      }
+    }
+  }
+
+  // Location of the i-th token; positions past the end report the last one.
+  fn location(&self, i: usize) -> String {
+    match self.tokens.get(i).or(self.tokens.last()) {
+      Some(t) => format_location(self.file.as_deref(), t.line, t.col),
+      None => "EOF".to_string(),
+    }
+  }
+
+  // A syntax error at the i-th token.
+  fn error_at(&self, i: usize, msg: &str) -> MalErr {
+    ErrString(format!("{} at {}", msg, self.location(i)))
+  }
+
+  // Adds the location of the i-th token to an error from read_atom or a
+  // collection constructor. Neither knows where it is, so their errors
+  // never carry a location already.
+  fn locate(&self, i: usize, e: MalErr) -> MalErr {
+    match e {
+      ErrString(s) => self.error_at(i, &s),
+      e => e,
+    }
+  }
+
+  // {:line l :column c :file f} for the i-th token.
+  fn meta(&self, i: usize) -> MalVal {
+    let t = &self.tokens[i];
+    let mut kvs = vec![
+      keyword("line"),
+      Int(t.line as i64),
+      keyword("column"),
+      Int(t.col as i64),
+    ];
+    if let Some(f) = &self.file {
+      kvs.extend([keyword("file"), Str(f.to_string())]);
+    }
+    hash_map(kvs).unwrap_or(Nil)
+  }
+}
+
+fn format_location(file: Option<&str>, line: usize, col: usize) -> String {
+  match file {
+    Some(f) => format!("{}:{}:{}", f, line, col),
+    None => format!("line {}, column {}", line, col),
+  }
+}
+
+// Where a form was read from, from the metadata read_coll gives it.
+// Symbols, and forms built by code rather than read, have none.
+pub fn form_location(form: &MalVal) -> Option<String> {
+  let meta = match form {
+    List(_, meta) | Vector(_, meta) | Hash(_, meta) | Set(_, meta) => meta,
+    _ => return None,
+  };
+  let Hash(hm, _) = &**meta else { return None };
+  let file = match hm.get(&keyword("file")) {
+    Some(Str(f)) => Some(f.as_str()),
+    _ => None,
+  };
+  match (hm.get(&keyword("line")), hm.get(&keyword("column"))) {
+    (Some(Int(line)), Some(Int(col))) => Some(format_location(file, *line as usize, *col as usize)),
+    _ => None,
   }
 }
 
-fn tokenize(str: &str) -> Vec<String> {
+// Byte ranges of the tokens in str, comments included.
+pub fn token_spans(str: &str) -> Vec<Range<usize>> {
   lazy_static! {
     static ref RE: Regex = Regex::new(
-      r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###
+      r###"[\s,]*(~@|#[({_]|[\[\]{}()'`~^@]|#?"(?:\\.|[^\\"])*"?|;.*|\\[^\s][^\s\[\]{}('"`,;)]*|[^\s\[\]{}('"`,;)]+)"###
     )
     .unwrap();
   }
 
+  RE.captures_iter(str).map(|cap| cap.get(1).unwrap().range()).collect()
+}
+
+// Lines and columns are 1-based; columns count characters.
+fn tokenize(str: &str) -> Vec<Token> {
   let mut res = vec![];
-  for cap in RE.captures_iter(str) {
-    if cap[1].starts_with(";") {
+  let (mut line, mut col, mut last) = (1, 1, 0);
+  for span in token_spans(str) {
+    for c in str[last..span.start].chars() {
+      if c == '\n' {
+        line += 1;
+        col = 1;
+      } else {
+        col += 1;
+      }
+    }
+    last = span.start;
+    if str[span.clone()].starts_with(';') {
       continue;
     }
-    res.push(String::from(&cap[1]));
+    res.push(Token {
+      text: String::from(&str[span]),
+      line,
+      col,
+    });
   }
   res
 }
 
-fn unescape_str(s: &str) -> String {
+// Supports \n \t \r \0 \e \\ \" \uXXXX and \u{X...}; anything else is an error.
+fn unescape_str(s: &str) -> Result<String, MalErr> {
+  let mut res = String::with_capacity(s.len());
+  let mut chars = s.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      res.push(c);
+      continue;
+    }
+    let esc = chars.next().unwrap_or('\\');
+    res.push(match esc {
+      'n' => '\n',
+      't' => '\t',
+      'r' => '\r',
+      '0' => '\0',
+      'e' => '\u{1b}',
+      '\\' | '"' => esc,
+      'u' => {
+        let rest = chars.as_str();
+        let braced = rest.starts_with('{');
+        let (hex, tail) = match rest.strip_prefix('{') {
+          Some(r) => match r.split_once('}') {
+            Some(parts) => parts,
+            None => return Err(ErrString("unterminated '\\u{' escape".to_string())),
+          },
+          None if rest.is_char_boundary(4.min(rest.len())) => rest.split_at(4.min(rest.len())),
+          None => (rest, ""),
+        };
+        let valid = hex.chars().all(|c| c.is_ascii_hexdigit())
+          && if braced { (1..=6).contains(&hex.len()) } else { hex.len() == 4 };
+        match u32::from_str_radix(hex, 16).ok().filter(|_| valid).and_then(char::from_u32) {
+          Some(c) => {
+            chars = tail.chars();
+            c
+          }
+          None => return Err(ErrString(format!("invalid unicode escape '\\u{}'", hex))),
+        }
+      }
+      _ => return Err(ErrString(format!("unknown escape '\\{}'", esc))),
+    });
+  }
+  Ok(res)
+}
+
+// Digit groups may be separated by single underscores, as in 1_000_000.
+fn read_number(token: &str) -> MalRet {
   lazy_static! {
-    static ref RE: Regex = Regex::new(r#"\\(.)"#).unwrap();
+    static ref INT_RE: Regex = Regex::new(
+      r"^([+-]?)(?:0[xX]([0-9a-fA-F]+(?:_[0-9a-fA-F]+)*)|0[bB]([01]+(?:_[01]+)*)|0[oO]([0-7]+(?:_[0-7]+)*)|([0-9]+)[rR]([0-9a-zA-Z]+(?:_[0-9a-zA-Z]+)*)|([0-9]+(?:_[0-9]+)*))$"
+    ).unwrap();
+    static ref RATIO_RE: Regex = Regex::new(
+      r"^([+-]?[0-9]+(?:_[0-9]+)*)/([0-9]+(?:_[0-9]+)*)$"
+    ).unwrap();
+    static ref FLT_RE: Regex = Regex::new(
+      r"^[+-]?(?:(?:D\.(?:D)?|\.D)(?:[eE][+-]?D)?|D[eE][+-]?D)$".replace('D', r"\d+(?:_\d+)*").as_str()
+    ).unwrap();
+  }
+  let invalid = || error(&format!("invalid number '{}'", token));
+  if let Some(caps) = INT_RE.captures(token) {
+    let (radix, digits) = match (caps.get(2), caps.get(3), caps.get(4), caps.get(6)) {
+      (Some(d), _, _, _) => (16, d),
+      (_, Some(d), _, _) => (2, d),
+      (_, _, Some(d), _) => (8, d),
+      (_, _, _, Some(d)) => match caps[5].parse() {
+        Ok(r) if (2..=36).contains(&r) => (r, d),
+        _ => return error(&format!("radix out of range in '{}'", token)),
+      },
+      _ => (10, caps.get(7).unwrap()),
+    };
+    let n = match Big::parse_bytes(digits.as_str().replace('_', "").as_bytes(), radix) {
+      Some(n) => n,
+      None => return invalid(),
+    };
+    let n = if &caps[1] == "-" { -n } else { n };
+    Ok(MalVal::from(Number::from(n)))
+  } else if let Some(caps) = RATIO_RE.captures(token) {
+    let numer: Big = caps[1].replace('_', "").parse().unwrap();
+    let denom: Big = caps[2].replace('_', "").parse().unwrap();
+    if denom.is_zero() {
+      return error(&format!("divide by zero in ratio '{}'", token));
+    }
+    Ok(MalVal::from(Number::from(BigRational::new(numer, denom))))
+  } else if FLT_RE.is_match(token) {
+    match token.replace('_', "").parse::<f64>() {
+      Ok(f) if f.is_finite() => Ok(Float(f)),
+      Ok(_) => error(&format!("number out of range '{}'", token)),
+      Err(_) => invalid(),
+    }
+  } else {
+    invalid()
+  }
+}
+
+// \a, \newline, \u00e9 ...; name is the token without its backslash.
+fn read_char(name: &str) -> MalRet {
+  let mut chars = name.chars();
+  if let (Some(c), None) = (chars.next(), chars.next()) {
+    return Ok(Char(c));
+  }
+  if let Some((_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+    return Ok(Char(*c));
+  }
+  let code = name
+    .strip_prefix('u')
+    .filter(|hex| hex.len() == 4)
+    .and_then(|hex| u32::from_str_radix(hex, 16).ok());
+  match code.map(char::from_u32) {
+    Some(Some(c)) => Ok(Char(c)),
+    Some(None) => error(&format!("invalid character code '\\{}'", name)),
+    None => error(&format!("unsupported character '\\{}'", name)),
   }
-  RE.replace_all(s, |caps: &Captures| {
-    if &caps[1] == "n" {"\n"} else { &caps[1] }.to_string()
-  })
-  .to_string()
 }
 
 fn read_atom(rdr: &mut Reader) -> MalRet {
   lazy_static! {
-    static ref INT_RE: Regex = Regex::new(r"^-?[0-9]+$").unwrap();
-    static ref FLT_RE: Regex = Regex::new(r"^[+-]?(?:(?:\d+\.\d*|\.\d+)(?:[eE][+-]?\d+)?|\d+[eE][+-]?\d+)$").unwrap();
+    static ref NUM_RE: Regex = Regex::new(r"^[+-]?\.?[0-9]").unwrap();
     static ref STR_RE: Regex = Regex::new(r#""(?:\\.|[^\\"])*""#).unwrap();
   }
   let token = rdr.next()?;
@@ -71,17 +281,24 @@ fn read_atom(rdr: &mut Reader) -> MalRet {
     "nil" => Ok(Nil),
     "false" => Ok(Bool(false)),
     "true" => Ok(Bool(true)),
+    "##Inf" => Ok(Float(f64::INFINITY)),
+    "##-Inf" => Ok(Float(f64::NEG_INFINITY)),
+    "##NaN" => Ok(Float(f64::NAN)),
     _ => {
-      if INT_RE.is_match(&token) {
-        Ok(Int(token.parse().unwrap()))
-      } else if FLT_RE.is_match(&token){
-        Ok(Float(token.parse().unwrap()))
+      if NUM_RE.is_match(&token) {
+        read_number(&token)
+      } else if let Some(name) = token.strip_prefix('\\') {
+        read_char(name)
+      } else if let Some(re) = token.strip_prefix('#').filter(|re| STR_RE.is_match(re)) {
+        // Regex literals are plain strings read without unescaping.
+        Ok(Str(re[1..re.len() - 1].to_string()))
       } else if STR_RE.is_match(&token) {
-        Ok(Str(unescape_str(&token[1..token.len() - 1])))
-      } else if token.starts_with('\"') {
+        Ok(Str(unescape_str(&token[1..token.len() - 1])?))
+      } else if token.starts_with('\"') || token.starts_with("#\"") {
+        rdr.eof = true;
         error("expected '\"', got EOF")
-      } else if let Some(keyword) = token.strip_prefix(':') {
-        Ok(Str(format!("\u{89e}{}", keyword)))
+      } else if let Some(keyword_name) = token.strip_prefix(':') {
+        Ok(keyword(keyword_name))
       } else {
         Ok(Sym(token.to_string()))
       }
@@ -89,13 +306,26 @@ fn read_atom(rdr: &mut Reader) -> MalRet {
   }
 }
 
-fn read_seq(rdr: &mut Reader, end: &str) -> MalRet {
+// Drops any #_ forms at the current position.
+fn skip_discarded(rdr: &mut Reader) -> Result<(), MalErr> {
+  while rdr.peek().is_ok_and(|t| t == "#_") {
+    let _ = rdr.next();
+    read_form(rdr)?;
+  }
+  Ok(())
+}
+
+fn read_seq(rdr: &mut Reader, end: &str) -> Result<Vec<MalVal>, MalErr> {
   let mut seq: Vec<MalVal> = vec![];
-  rdr.next()?;
+  let start = rdr.pos;
+  let open = rdr.next()?;
   loop {
+    skip_discarded(rdr)?;
     let token = match rdr.peek() {
       Ok(t) => t,
-      Err(_) => return error(&format!("expected '{}', got EOF", end))
+      Err(_) => return Err(rdr.error_at(start, &format!(
+        "expected '{}', got EOF for '{}'", end, open
+      ))),
     };
     if token == end {
       break;
@@ -104,12 +334,80 @@ fn read_seq(rdr: &mut Reader, end: &str) -> MalRet {
   }
 
   let _ = rdr.next();
-  match end {
-    ")" => Ok(list!(seq)),
-    "]" => Ok(vector!(seq)),
-    "}" => hash_map(seq),
-    _   => error("read_seq unknown end value"),
+  Ok(seq)
+}
+
+// Reads a bracketed form and tags it with its location.
+fn read_coll(rdr: &mut Reader, end: &str, make: fn(Vec<MalVal>) -> MalRet) -> MalRet {
+  let start = rdr.pos;
+  let seq = read_seq(rdr, end)?;
+  make(seq).map_err(|e| rdr.locate(start, e))?.with_meta(&rdr.meta(start))
+}
+
+// The highest %n a #() form may use, as in Clojure.
+const MAX_ANON_ARGS: usize = 20;
+
+// #(+ % %2) reads as (fn* [%1 %2] (+ %1 %2)); %& collects the rest.
+fn read_anon_fn(rdr: &mut Reader) -> MalRet {
+  let start = rdr.pos;
+  if rdr.in_anon_fn {
+    return Err(rdr.error_at(start, "nested #()s are not allowed"));
   }
+  rdr.in_anon_fn = true;
+  let body = read_seq(rdr, ")");
+  rdr.in_anon_fn = false;
+  let (mut max, mut rest) = (0, false);
+  let body = anon_fn_body(&list!(body?), &mut max, &mut rest)
+    .map_err(|e| rdr.locate(start, e))?;
+  let mut params: Vec<MalVal> = (1..=max).map(|i| Sym(format!("%{}", i))).collect();
+  if rest {
+    params.extend([Sym("&".to_string()), Sym("%&".to_string())]);
+  }
+  list![Sym("fn*".to_string()), vector!(params), body].with_meta(&rdr.meta(start))
+}
+
+// Renames % to %1 and records the highest %n used and whether %& is.
+// %n must be between %1 and %20.
+fn anon_fn_body(form: &MalVal, max: &mut usize, rest: &mut bool) -> MalRet {
+  let mut walk = |v: &Rc<Vec<MalVal>>| -> Result<Rc<Vec<MalVal>>, MalErr> {
+    Ok(Rc::new(v.iter().map(|f| anon_fn_body(f, max, rest)).collect::<Result<_, _>>()?))
+  };
+  Ok(match form {
+    Sym(s) if s == "%" => {
+      *max = (*max).max(1);
+      Sym("%1".to_string())
+    }
+    Sym(s) if s == "%&" => {
+      *rest = true;
+      form.clone()
+    }
+    Sym(s) => {
+      let digits = s.strip_prefix('%');
+      if let Some(n) = digits.filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())) {
+        match n.parse::<usize>() {
+          Ok(n) if (1..=MAX_ANON_ARGS).contains(&n) => *max = (*max).max(n),
+          _ => {
+            return error(&format!("invalid #() argument {}, expecting %1 to %{}", s, MAX_ANON_ARGS))
+          }
+        }
+      }
+      form.clone()
+    }
+    List(v, meta) => List(walk(v)?, meta.clone()),
+    Vector(v, meta) => Vector(walk(v)?, meta.clone()),
+    Set(v, meta) => {
+      let set = v.iter().map(|f| anon_fn_body(f, max, rest)).collect::<Result<_, _>>()?;
+      Set(Rc::new(set), meta.clone())
+    }
+    Hash(hm, meta) => {
+      let mut entries = MalMap::default();
+      for (k, v) in hm.iter() {
+        entries.insert(anon_fn_body(k, max, rest)?, anon_fn_body(v, max, rest)?);
+      }
+      Hash(Rc::new(entries), meta.clone())
+    }
+    _ => form.clone(),
+  })
 }
 
 fn read_form(rdr: &mut Reader) -> MalRet {
@@ -140,23 +438,185 @@ fn read_form(rdr: &mut Reader) -> MalRet {
       let _ = rdr.next();
       Ok(list![Sym("deref".to_string()), read_form(rdr)?])
     },
-    ")" => error("unexpected ')'"),
-    "(" => read_seq(rdr, ")"),
-    "]" => error("unexpected ']'"),
-    "[" => read_seq(rdr, "]"),
-    "}" => error("unexpected '}'"),
-    "{" => read_seq(rdr, "}"),
-    _   => read_atom(rdr),
+    ")" | "]" | "}" => {
+      Err(rdr.error_at(rdr.pos, &format!("unexpected '{}'", token)))
+    }
+    "(" => read_coll(rdr, ")", |seq| Ok(list!(seq))),
+    "[" => read_coll(rdr, "]", |seq| Ok(vector!(seq))),
+    "{" => read_coll(rdr, "}", hash_map),
+    "#{" => read_coll(rdr, "}", |seq| Ok(hash_set(seq))),
+    "#(" => read_anon_fn(rdr),
+    "#_" => {
+      skip_discarded(rdr)?;
+      read_form(rdr)
+    }
+    _   => {
+      let at = rdr.pos;
+      read_atom(rdr).map_err(|e| rdr.locate(at, e))
+    }
   }
 }
 
 pub fn read_str(str: &str) -> MalRet {
-  let tokens = tokenize(str);
-  if tokens.is_empty() {
+  read_str_from(str, None)
+}
+
+// Like read_str, but locations in errors and metadata name the file.
+pub fn read_str_from(str: &str, file: Option<&str>) -> MalRet {
+  let mut rdr = Reader::new(str, file);
+  if rdr.tokens.is_empty() {
     return error("no input");
   }
-  read_form(&mut Reader {
-    pos: 0,
-    tokens
-  })
-}
\ No newline at end of file
+  read_form(&mut rdr)
+}
+
+// Reads every top-level form; empty input (or only comments) gives none.
+pub fn read_all(str: &str, file: Option<&str>) -> Result<Vec<MalVal>, MalErr> {
+  let mut rdr = Reader::new(str, file);
+  let mut forms = vec![];
+  loop {
+    skip_discarded(&mut rdr)?;
+    if rdr.pos >= rdr.tokens.len() {
+      return Ok(forms);
+    }
+    forms.push(read_form(&mut rdr)?);
+  }
+}
+
+// True when str stops in the middle of a form, e.g. inside an unclosed
+// list or string, so that more input could still make it readable.
+pub fn is_incomplete(str: &str) -> bool {
+  let mut rdr = Reader::new(str, None);
+  loop {
+    let res = skip_discarded(&mut rdr);
+    if res.is_ok() && rdr.pos >= rdr.tokens.len() {
+      return false;
+    }
+    if res.and_then(|_| read_form(&mut rdr)).is_err() {
+      return rdr.eof;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::types::MalVal::BigInt;
+
+  #[test]
+  fn test_integer_literals() {
+    assert!(matches!(read_str("+5"), Ok(Int(5))));
+    assert!(matches!(read_str("-0xff"), Ok(Int(-255))));
+    assert!(matches!(read_str("0b1010"), Ok(Int(10))));
+    assert!(matches!(read_str("0o17"), Ok(Int(15))));
+    assert!(matches!(read_str("36rZZ"), Ok(Int(1295))));
+    assert!(matches!(read_str("1_000_000"), Ok(Int(1_000_000))));
+    assert!(matches!(read_str("0xFFFF_FFFF_FFFF_FFFF_FF"), Ok(BigInt(_))));
+  }
+
+  #[test]
+  fn test_float_literals() {
+    assert!(matches!(read_str("1e3"), Ok(Float(f)) if f == 1000.0));
+    assert!(matches!(read_str("1_000.5"), Ok(Float(f)) if f == 1000.5));
+    assert!(matches!(read_str("##Inf"), Ok(Float(f)) if f == f64::INFINITY));
+    assert!(matches!(read_str("##NaN"), Ok(Float(f)) if f.is_nan()));
+  }
+
+  #[test]
+  fn test_invalid_number_literals() {
+    assert!(read_str("1e999").is_err());
+    assert!(read_str("37r10").is_err());
+    assert!(read_str("2r12").is_err());
+    assert!(read_str("1__0").is_err());
+    assert!(read_str("1/0").is_err());
+    assert!(matches!(read_str("-"), Ok(Sym(_))));
+    assert!(matches!(read_str("+foo"), Ok(Sym(_))));
+  }
+  #[test]
+  fn test_error_locations() {
+    let err = |s: &str| match read_str(s) {
+      Err(ErrString(e)) => e,
+      _ => panic!("expected error reading {}", s),
+    };
+    assert_eq!(err("(1\n  (2 3"), "expected ')', got EOF for '(' at line 2, column 3");
+    assert_eq!(err("(1\n ]"), "unexpected ']' at line 2, column 2");
+    assert_eq!(err("(1 0xZZ)"), "invalid number '0xZZ' at line 1, column 4");
+    assert_eq!(err("[{1}]"), "odd number of elements at line 1, column 2");
+    match read_str_from("\n'", Some("f.mal")) {
+      Err(ErrString(e)) => assert_eq!(e, "unexpected EOF at f.mal:2:1"),
+      _ => panic!("expected error"),
+    }
+  }
+
+  #[test]
+  fn test_read_all() {
+    assert!(matches!(read_all("1 (2 3) ; trailing comment\n", None), Ok(ref v) if v.len() == 2));
+    assert!(matches!(read_all("  ; nothing\n", None), Ok(ref v) if v.is_empty()));
+    match read_all("(a)\n)", Some("f.mal")) {
+      Err(ErrString(e)) => assert_eq!(e, "unexpected ')' at f.mal:2:1"),
+      _ => panic!("expected error"),
+    }
+  }
+
+  #[test]
+  fn test_location_metadata() {
+    let form = read_str_from("(a\n [b])", Some("f.mal")).ok().unwrap();
+    let inner = match form {
+      List(ref l, _) => l[1].clone(),
+      _ => panic!("expected list"),
+    };
+    let meta = inner.get_meta().ok().unwrap();
+    assert!(meta == hash_map(vec![
+      keyword("line"), Int(2), keyword("column"), Int(2), keyword("file"), Str("f.mal".to_string()),
+    ]).ok().unwrap());
+  }
+  #[test]
+  fn test_dispatch_macros() {
+    let pr = |s: &str| read_str(s).ok().unwrap().pr_str(true);
+    assert_eq!(pr("#(+ % %3 %&)"), "(fn* [%1 %2 %3 & %&] (+ %1 %3 %&))");
+    assert_eq!(pr("#(do [%] {:a %2})"), "(fn* [%1 %2] (do [%1] {:a %2}))");
+    assert_eq!(pr("#{1 2 1}"), "#{1 2}");
+    assert_eq!(pr("(1 #_2 #_ #_3 4 5)"), "(1 5)");
+    assert_eq!(pr("#_x y"), "y");
+    assert_eq!(pr("#\"\\d+\""), "\"\\\\d+\"");
+    assert!(read_str("#(#(%))").is_err());
+    assert!(matches!(read_str("#(%20)"), Ok(List(..))));
+    for bad in ["#(%21)", "#(%0)", "#(%99999999999)", "#([%99999999999999999999999])"] {
+      assert!(read_str(bad).is_err(), "{}", bad);
+    }
+    assert!(read_str("#\"abc").is_err());
+    assert!(matches!(read_all("1 #_2", None), Ok(ref v) if v.len() == 1));
+  }
+  #[test]
+  fn test_char_literals() {
+    assert!(matches!(read_str("\\a"), Ok(Char('a'))));
+    assert!(matches!(read_str("\\newline"), Ok(Char('\n'))));
+    assert!(matches!(read_str("\\u00e9"), Ok(Char('é'))));
+    assert!(matches!(read_str("\\é"), Ok(Char('é'))));
+    assert!(matches!(read_str("(\\))"), Ok(List(ref l, _)) if matches!(l[0], Char(')'))));
+    assert!(read_str("\\foo").is_err());
+    assert!(read_str("\\ud800").is_err());
+    for c in ['a', ' ', '\n', '\\', '(', '\u{1}', 'é', '\u{a0}', '\u{2028}', '\u{3000}'] {
+      assert!(matches!(read_str(&Char(c).pr_str(true)), Ok(Char(r)) if r == c));
+    }
+  }
+  #[test]
+  fn test_string_escapes() {
+    assert!(matches!(read_str(r#""a\tb\r\0\eé\u{1F600}\"\\""#), Ok(Str(ref s)) if s == "a\tb\r\0\x1bé😀\"\\"));
+    assert!(read_str(r#""\q""#).is_err());
+    assert!(read_str(r#""\u12""#).is_err());
+    assert!(read_str(r#""\u{110000}""#).is_err());
+    assert!(read_str(r#""\u{41""#).is_err());
+    let s = "tab\t nul\0 esc\x1b del\x7f bell\x07 \"q\" \\ é 😀\n".to_string();
+    assert!(matches!(read_str(&Str(s.clone()).pr_str(true)), Ok(Str(ref r)) if *r == s));
+  }
+  #[test]
+  fn test_is_incomplete() {
+    for s in ["(def! f (fn* (x)", "[1 2", "\"abc", "'", "(a #_", "{:a \"b\\\""] {
+      assert!(is_incomplete(s), "{}", s);
+    }
+    for s in ["", "; comment", "(+ 1 2)", "(a))", "0xZZ", "(1 2) 3", "\\("] {
+      assert!(!is_incomplete(s), "{}", s);
+    }
+  }
+}
@@ -2,7 +2,6 @@
 
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
 use itertools::Itertools;
 
 
@@ -16,8 +15,8 @@ extern crate rustyline;
 use crate::reader;
 
 use crate::types::MalErr::{ErrMalVal, ErrString};
-use crate::types::MalVal::{Bool, Func, Hash, List, MalFunc, Nil, Str, Sym, Vector};
-use crate::types::{error, MalArgs, MalErr, MalRet, MalVal};
+use crate::types::MalVal::{Bool, Func, Hash, List, MalFunc, Nil, Set, Str, Sym, Vector};
+use crate::types::{error, hash_set, MalArgs, MalErr, MalMap, MalRet, MalVal};
 
 use crate::env::{env_bind, env_find_repl, env_get, env_new, env_set, Env};
 
@@ -53,11 +52,45 @@ fn quasiquote(ast: &MalVal) -> MalVal {
             qq_iter(v)
         }
         Vector(v, _) => list![Sym("vec".to_string()), qq_iter(v)],
-        Hash(_, _) | Sym(_) => list![Sym("quote".to_string()), ast.clone()],
+        Hash(_, _) | Set(_, _) | Sym(_) => list![Sym("quote".to_string()), ast.clone()],
         _ => ast.clone(),
     }
 }
 
+// Symbols eval handles itself rather than looking up in the environment.
+pub const SPECIAL_FORMS: &[&str] = &[
+    "def!",
+    "let*",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "splice-unquote",
+    "defmacro!",
+    "try*",
+    "catch*",
+    "do",
+    "if",
+    "fn*",
+    "eval",
+];
+
+// msg followed by where form was read, if that is known.
+fn error_at(msg: &str, form: &MalVal) -> MalErr {
+    match reader::form_location(form) {
+        Some(loc) => ErrString(format!("{} at {}", msg, loc)),
+        None => ErrString(msg.to_string()),
+    }
+}
+
+// Evaluates an element of the list form. Symbols carry no location of
+// their own, so a symbol that is not found is reported at the list's.
+fn eval_elem(elem: &MalVal, env: &Env, form: &MalVal) -> MalRet {
+    match (eval(elem, env), elem) {
+        (Err(_), Sym(s)) => Err(error_at(&format!("'{}' not found", s), form)),
+        (res, _) => res,
+    }
+}
+
 pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
     let mut ast = orig_ast;
     let mut env = orig_env;
@@ -83,10 +116,17 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                 }
                 return Ok(vector!(lst));
             }
+            Set(v, _) => {
+                let mut lst: MalArgs = vec![];
+                for a in v.iter() {
+                    lst.push(eval(a, env)?);
+                }
+                return Ok(hash_set(lst));
+            }
             Hash(hm, _) => {
-                let mut new_hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+                let mut new_hm = MalMap::default();
                 for (k, v) in hm.iter() {
-                    new_hm.insert(k.to_string(), eval(v, env)?);
+                    new_hm.insert(eval(k, env)?, eval(v, env)?);
                 }
                 return Ok(Hash(Rc::new(new_hm), Rc::new(Nil)));
             }
@@ -97,7 +137,7 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                 let a0 = &l[0];
                 match a0 {
                     Sym(a0sym) if a0sym == "def!" => {
-                        return env_set(env, &l[1], eval(&l[2], env)?);
+                        return env_set(env, &l[1], eval_elem(&l[2], env, ast)?);
                     }
                     Sym(a0sym) if a0sym == "let*" => {
                         live_env = env_new(Some(env.clone()));
@@ -106,7 +146,7 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                         match a1 {
                             List(binds, _) | Vector(binds, _) => {
                                 for (b, e) in binds.iter().tuples() {
-                                    let val = eval(e, env)?;
+                                    let val = eval_elem(e, env, ast)?;
                                     env_set(env, b, val)?;
                                 }
                             }
@@ -126,7 +166,7 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                     }
                     Sym(a0sym) if a0sym == "defmacro!" => {
                         let (a1, a2) = (&l[1], &l[2]);
-                        let r = eval(a2, env)?;
+                        let r = eval_elem(a2, env, ast)?;
                         match r {
                             MalFunc {
                                 eval,
@@ -179,15 +219,18 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                         }
                     }
                     Sym(a0sym) if a0sym == "do" => {
+                        if l.len() == 1 {
+                            return Ok(Nil);
+                        }
                         for i in 1..l.len() - 1 {
-                            let _ = eval(&l[i], env)?;
+                            let _ = eval_elem(&l[i], env, ast)?;
                         }
                         live_ast = l.last().unwrap_or(&Nil).clone();
                         ast = &live_ast;
                         continue 'tco;
                     }
                     Sym(a0sym) if a0sym == "if" => {
-                        let cond = eval(&l[1], env)?;
+                        let cond = eval_elem(&l[1], env, ast)?;
                         match cond {
                             Bool(false) | Nil if l.len() >= 4 => {
                                 live_ast = l[3].clone();
@@ -217,13 +260,13 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                     Sym(a0sym) if a0sym == "eval" => {
                         //  Hard to implement without global variables.
                         //  Normal argument evaluation.
-                        live_ast = eval(&l[1], env)?;
+                        live_ast = eval_elem(&l[1], env, ast)?;
                         ast = &live_ast;
                         live_env = env_find_repl(env);
                         env = &live_env;
                         continue 'tco;
                     }
-                    _ => match eval(a0, env) {
+                    _ => match eval_elem(a0, env, ast) {
                         Ok(f @ MalFunc { is_macro: true, .. }) => {
                             let new_ast = f.apply(l[1..].to_vec())?;
                             live_ast = new_ast;
@@ -233,7 +276,7 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                         Ok(f @ Func(_, _)) => {
                             let mut args: MalArgs = vec![];
                             for i in 1..l.len() {
-                                args.push(eval(&l[i], env)?);
+                                args.push(eval_elem(&l[i], env, ast)?);
                             }
                             return f.apply(args);
                         }
@@ -245,7 +288,7 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                         }) => {
                             let mut args: MalArgs = vec![];
                             for i in 1..l.len() {
-                                args.push(eval(&l[i], env)?);
+                                args.push(eval_elem(&l[i], env, ast)?);
                             }
                             live_env = env_bind(Some(menv.clone()), &mparams, args.to_vec())?;
                             env = &live_env;
@@ -253,7 +296,7 @@ pub fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
                             ast = &live_ast;
                             continue 'tco;
                         }
-                        Ok(_) => return error("attempt to call non-function"),
+                        Ok(_) => return Err(error_at("attempt to call non-function", ast)),
                         e @ Err(_) => return e,
                     },
                 }
@@ -271,12 +314,13 @@ pub fn print(ast: &MalVal) -> String {
     ast.pr_str(true)
 }
 
-pub fn rep(str: &str, env: &Env, num: &i32) -> Result<String, MalErr> {
-    let ast = read(str)?;
-    let exp = eval(&ast, env)?;
-    let key = MalVal::Sym(format!("${}", num));
-    let val= env_set(env, &key, exp)?;
-    Ok(print(&val))
+// Evaluates every form in str, returning the value of the last one.
+pub fn eval_all(str: &str, file: Option<&str>, env: &Env) -> MalRet {
+    let mut res = Nil;
+    for ast in reader::read_all(str, file)? {
+        res = eval(&ast, env)?;
+    }
+    Ok(res)
 }
 
 pub fn re(str: &str, env: &Env) {
@@ -286,4 +330,22 @@ pub fn re(str: &str, env: &Env) {
         }
     }
     panic!("Error during startup");
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn err(src: &str) -> String {
+        match eval_all(src, Some("f.mal"), &env_new(None)) {
+            Err(ErrString(e)) => e,
+            _ => panic!("expected error evaluating {}", src),
+        }
+    }
+
+    #[test]
+    fn test_error_locations() {
+        assert_eq!(err("(do\n  (undefined-thing))"), "'undefined-thing' not found at f.mal:2:3");
+        assert_eq!(err("(do 1\n (2 3))"), "attempt to call non-function at f.mal:2:2");
+        assert_eq!(err("undefined-thing"), "'undefined-thing' not found");
+    }
+}
@@ -0,0 +1,402 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::time::Instant;
+
+use rustyline::config::Configurer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Editor, EventHandler, KeyCode, KeyEvent, Modifiers};
+
+use crate::env::{env_get, env_keys, env_sets, Env};
+use crate::helper::{ContinueLine, MalHelper};
+use crate::pprint::{pprint_str, DEFAULT_WIDTH};
+use crate::reader::{is_incomplete, read_all};
+use crate::rep::{eval, eval_all, print, SPECIAL_FORMS};
+use crate::types::MalErr::{ErrMalVal, ErrString};
+use crate::types::MalVal::{Func, Hash, Int, MalFunc, Nil, Str};
+use crate::types::{error, format_error, func, keyword, MalArgs, MalErr, MalRet, MalVal};
+
+// How many results (result n) can reach back.
+const RESULT_RING: usize = 100;
+
+thread_local! {
+    // REPL results, most recent first.
+    static RESULTS: RefCell<VecDeque<MalVal>> = const { RefCell::new(VecDeque::new()) };
+}
+
+// Records a REPL result and rebinds *1, *2 and *3 to the last three.
+fn push_result(env: &Env, val: MalVal) {
+    RESULTS.with_borrow_mut(|results| {
+        results.push_front(val);
+        results.truncate(RESULT_RING);
+        for (i, name) in ["*1", "*2", "*3"].iter().enumerate() {
+            env_sets(env, name, results.get(i).cloned().unwrap_or(Nil));
+        }
+    });
+}
+
+// (result n) is the n-th most recent REPL result; (result 1) is *1.
+fn result(a: MalArgs) -> MalRet {
+    let n = match a[0] {
+        Int(n) if n >= 1 => n as usize,
+        _ => return error("result: expecting positive integer"),
+    };
+    match RESULTS.with_borrow(|results| results.get(n - 1).cloned()) {
+        Some(v) => Ok(v),
+        None => Err(ErrString(format!("result: no result {}", n))),
+    }
+}
+
+// A meta-command handler gets the REPL and the rest of the line.
+pub type Handler = fn(&mut Repl, &str) -> Result<(), MalErr>;
+
+struct Command {
+    name: &'static str,
+    usage: &'static str,
+    help: &'static str,
+    handler: Handler,
+}
+
+pub struct Repl {
+    pub rl: Editor<MalHelper, DefaultHistory>,
+    pub env: Env,
+    // The file most recently loaded with :load, for :reload.
+    pub last_load: Option<String>,
+    pub quit: bool,
+    // Print results with pprint instead of on one line.
+    pub pretty: bool,
+    // False when stdin is not a terminal: no prompt, banner or history.
+    interactive: bool,
+    history_file: Option<PathBuf>,
+    commands: Vec<Command>,
+}
+
+impl Repl {
+    pub fn new(env: Env, interactive: bool, history_file: Option<PathBuf>) -> Repl {
+        let mut rl = Editor::<MalHelper, DefaultHistory>::new().unwrap();
+        let history_file = history_file.filter(|_| interactive);
+        if let Some(h) = &history_file {
+            if rl.load_history(h).is_err() {
+                eprintln!("No previous history");
+            }
+        }
+        rl.set_color_mode(rustyline::ColorMode::Enabled);
+        rl.set_helper(Some(MalHelper::new(env.clone())));
+        rl.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(ContinueLine)),
+        );
+
+        let mut repl = Repl {
+            rl,
+            env,
+            last_load: None,
+            quit: false,
+            pretty: false,
+            interactive,
+            history_file,
+            commands: vec![],
+        };
+        for name in ["*1", "*2", "*3", "*e"] {
+            env_sets(&repl.env, name, Nil);
+        }
+        env_sets(&repl.env, "result", func(result));
+        repl.register("help", "", "list the REPL commands", help);
+        repl.register("doc", "sym", "describe what sym is bound to", doc);
+        repl.register("env", "[prefix]", "list the names bound in the environment", list_env);
+        repl.register("time", "expr", "evaluate expr and report how long it took", time);
+        repl.register("load", "file", "load a file of mal code", load);
+        repl.register("reload", "", "load the last :load file again", reload);
+        repl.register("pprint", "[on|off]", "pretty-print results, or toggle", pretty);
+        repl.register("history", "", "list the input history", history);
+        repl.register("clear", "", "clear the screen", clear);
+        repl.register("quit", "", "leave the REPL", quit);
+        repl
+    }
+
+    // Adds a command, replacing any existing one with the same name.
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        help: &'static str,
+        handler: Handler,
+    ) {
+        self.commands.retain(|c| c.name != name);
+        self.commands.push(Command {
+            name,
+            usage,
+            help,
+            handler,
+        });
+    }
+
+    // Only registered names are commands, so keywords such as :foo are
+    // still evaluated.
+    fn command(&self, line: &str) -> Option<(Handler, String)> {
+        let (name, arg) = split_command(line)?;
+        let cmd = self.commands.iter().find(|c| c.name == name)?;
+        Some((cmd.handler, arg.to_string()))
+    }
+
+    // Records an error in *e.
+    fn set_error(&self, e: MalErr) -> MalErr {
+        let val = match &e {
+            ErrString(s) => Str(s.clone()),
+            ErrMalVal(v) => v.clone(),
+        };
+        env_sets(&self.env, "*e", val);
+        e
+    }
+
+    // Reads every form on line; a syntax error is recorded in *e.
+    pub fn read(&self, line: &str) -> Result<Vec<MalVal>, MalErr> {
+        read_all(line, None).map_err(|e| self.set_error(e))
+    }
+
+    // Evaluates one form, recording the value in *1 or the error in *e.
+    pub fn eval_form(&mut self, ast: &MalVal) -> Result<String, MalErr> {
+        match eval(ast, &self.env) {
+            Ok(val) => {
+                push_result(&self.env, val.clone());
+                Ok(match self.pretty {
+                    true => pprint_str(&val, DEFAULT_WIDTH),
+                    false => print(&val),
+                })
+            }
+            Err(e) => Err(self.set_error(e)),
+        }
+    }
+
+    // Evaluates and prints each form on line in turn, stopping at the
+    // first error.
+    pub fn eval(&mut self, line: &str) -> Result<(), MalErr> {
+        for ast in self.read(line)? {
+            println!("{}", self.eval_form(&ast)?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) {
+        if !self.interactive {
+            return self.run_piped();
+        }
+        while !self.quit {
+            let line = match self.rl.readline("user> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => {
+                    println!("{:?}", err);
+                    break;
+                }
+            };
+            let _ = self.rl.add_history_entry(&line);
+            self.save_history();
+            self.handle(&line);
+        }
+    }
+
+    // Failures are ignored so that an unwritable history file does not
+    // break the session.
+    fn save_history(&mut self) {
+        if let Some(h) = &self.history_file {
+            if let Some(dir) = h.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = self.rl.save_history(h);
+        }
+    }
+
+    // Reads stdin a line at a time, handling each form once it is complete.
+    fn run_piped(&mut self) {
+        let mut input = String::new();
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            input.push_str(&line);
+            input.push('\n');
+            if !is_incomplete(&input) {
+                self.handle(input.trim_end());
+                input.clear();
+            }
+            if self.quit {
+                return;
+            }
+        }
+        // Let the reader report whatever was left unfinished.
+        self.handle(input.trim_end());
+    }
+
+    fn handle(&mut self, line: &str) {
+        if line.trim().is_empty() {
+            return;
+        }
+        let res = match self.command(line) {
+            Some((handler, arg)) => handler(self, &arg),
+            None => self.eval(line),
+        };
+        if let Err(e) = res {
+            println!("Error: {}", format_error(e));
+        }
+    }
+}
+
+// Splits ":name arg" into name and arg. The colon must be the first
+// character, so " :help" with leading space is evaluated as a keyword.
+fn split_command(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix(':')?;
+    let (name, arg) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((name, arg.trim()))
+}
+
+fn help(repl: &mut Repl, _arg: &str) -> Result<(), MalErr> {
+    for c in &repl.commands {
+        let call = format!(":{} {}", c.name, c.usage);
+        println!("  {:<16} {}", call.trim_end(), c.help);
+    }
+    println!("Commands must start the line; anything else is evaluated.");
+    Ok(())
+}
+
+fn doc(repl: &mut Repl, arg: &str) -> Result<(), MalErr> {
+    if arg.is_empty() {
+        return Err(ErrString(":doc: expecting a symbol".to_string()));
+    }
+    if SPECIAL_FORMS.contains(&arg) {
+        println!("{}: special form", arg);
+        return Ok(());
+    }
+    match env_get(&repl.env, arg) {
+        Some(MalFunc {
+            params,
+            is_macro,
+            meta,
+            ..
+        }) => {
+            let kind = if is_macro { "macro" } else { "function" };
+            println!("({} {}): {}", arg, params.pr_str(true), kind);
+            if let Hash(hm, _) = &*meta {
+                if let Some(Str(s)) = hm.get(&keyword("doc")) {
+                    println!("  {}", s);
+                }
+            }
+        }
+        Some(Func(..)) => println!("{}: built-in function", arg),
+        Some(v) => println!("{}: {}", arg, v.pr_str(true)),
+        None => return Err(ErrString(format!("'{}' not found", arg))),
+    }
+    Ok(())
+}
+
+fn list_env(repl: &mut Repl, arg: &str) -> Result<(), MalErr> {
+    let mut names: Vec<String> = env_keys(&repl.env)
+        .into_iter()
+        .filter(|k| k.starts_with(arg))
+        .collect();
+    names.sort();
+    names.dedup();
+    println!("{}", names.join(" "));
+    Ok(())
+}
+
+fn time(repl: &mut Repl, arg: &str) -> Result<(), MalErr> {
+    for ast in repl.read(arg)? {
+        let start = Instant::now();
+        let res = repl.eval_form(&ast);
+        let elapsed = start.elapsed();
+        println!("{}", res?);
+        println!("Elapsed time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+    }
+    Ok(())
+}
+
+fn load(repl: &mut Repl, arg: &str) -> Result<(), MalErr> {
+    if arg.is_empty() {
+        return Err(ErrString(":load: expecting a file name".to_string()));
+    }
+    repl.last_load = Some(arg.to_string());
+    let form = format!("(load-file {})", Str(arg.to_string()).pr_str(true));
+    eval_all(&form, None, &repl.env)?;
+    println!("Loaded {}", arg);
+    Ok(())
+}
+
+fn reload(repl: &mut Repl, _arg: &str) -> Result<(), MalErr> {
+    match repl.last_load.clone() {
+        Some(f) => load(repl, &f),
+        None => Err(ErrString(":reload: nothing loaded yet".to_string())),
+    }
+}
+
+fn pretty(repl: &mut Repl, arg: &str) -> Result<(), MalErr> {
+    repl.pretty = match arg {
+        "on" => true,
+        "off" => false,
+        "" => !repl.pretty,
+        _ => return Err(ErrString(":pprint: expecting on or off".to_string())),
+    };
+    println!("Pretty printing {}", if repl.pretty { "on" } else { "off" });
+    Ok(())
+}
+
+fn history(repl: &mut Repl, _arg: &str) -> Result<(), MalErr> {
+    for (i, line) in repl.rl.history().iter().enumerate() {
+        println!("{:>4}  {}", i + 1, line);
+    }
+    Ok(())
+}
+
+fn clear(repl: &mut Repl, _arg: &str) -> Result<(), MalErr> {
+    repl.rl
+        .clear_screen()
+        .map_err(|e| ErrString(format!(":clear: {}", e)))
+}
+
+fn quit(repl: &mut Repl, _arg: &str) -> Result<(), MalErr> {
+    repl.quit = true;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_results() {
+        let env = crate::env::env_new(None);
+        for i in 0..RESULT_RING as i64 + 5 {
+            push_result(&env, Int(i));
+        }
+        let last = RESULT_RING as i64 + 4;
+        assert!(matches!(env_get(&env, "*1"), Some(Int(n)) if n == last));
+        assert!(matches!(env_get(&env, "*3"), Some(Int(n)) if n == last - 2));
+        assert!(matches!(result(vec![Int(2)]), Ok(Int(n)) if n == last - 1));
+        assert!(matches!(result(vec![Int(RESULT_RING as i64)]), Ok(Int(5))));
+        assert!(result(vec![Int(RESULT_RING as i64 + 1)]).is_err());
+        assert!(result(vec![Int(0)]).is_err());
+    }
+
+    #[test]
+    fn test_eval_every_form() {
+        let mut repl = Repl::new(crate::env::env_new(None), false, None);
+        assert!(repl.eval("(def! a 1) (def! b 2) 3").is_ok());
+        assert!(matches!(env_get(&repl.env, "b"), Some(Int(2))));
+        assert!(matches!(env_get(&repl.env, "*1"), Some(Int(3))));
+        assert!(matches!(env_get(&repl.env, "*3"), Some(Int(1))));
+
+        assert!(repl.eval("4 (undefined) 5").is_err());
+        assert!(matches!(env_get(&repl.env, "*1"), Some(Int(4))));
+        assert!(matches!(env_get(&repl.env, "*e"), Some(Str(_))));
+    }
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command(":quit"), Some(("quit", "")));
+        assert_eq!(split_command(":time  (+ 1 2) "), Some(("time", "(+ 1 2)")));
+        assert_eq!(split_command(" :help"), None);
+        assert_eq!(split_command("(:a {:b 1})"), None);
+    }
+}
@@ -1,25 +1,42 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 //use std::collections::HashMap;
-use fnv::FnvHashMap;
+use std::hash::{self, Hash as _, Hasher};
+
+use fnv::{FnvBuildHasher, FnvHasher};
+use indexmap::{IndexMap, IndexSet};
 use itertools::Itertools;
 
 use crate::env::{env_bind, Env};
 use crate::types::MalErr::{ErrMalVal, ErrString};
-use crate::types::MalVal::{Atom, Bool, Int, Float, Func, Hash, List, MalFunc, Nil, Str, Sym, Vector};
+use crate::types::MalVal::{
+    Atom, BigInt, Bool, Char, Float, Func, Hash, Int, List, MalFunc, Nil, Ratio, Set, Str, Sym,
+    Vector,
+};
+
+
+// Maps iterate, print and compare keys in insertion order; equality
+// ignores order.
+pub type MalMap = IndexMap<MalVal, MalVal, FnvBuildHasher>;
 
+// Sets likewise keep their elements in first-seen order.
+pub type MalSet = IndexSet<MalVal, FnvBuildHasher>;
 
 #[derive(Clone)]
 pub enum MalVal {
     Nil,
     Bool(bool),
     Int(i64),
+    BigInt(num_bigint::BigInt),
+    Ratio(num_rational::BigRational),
     Float(f64),
+    Char(char),
     Str(String),
     Sym(String),
     List(Rc<Vec<MalVal>>, Rc<MalVal>),
     Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
-    Hash(Rc<FnvHashMap<String, MalVal>>, Rc<MalVal>),
+    Hash(Rc<MalMap>, Rc<MalVal>),
+    Set(Rc<MalSet>, Rc<MalVal>),
     Func(fn(MalArgs) -> MalRet, Rc<MalVal>),
     MalFunc {
         eval: fn(ast: &MalVal, env: &Env) -> MalRet,
@@ -77,6 +94,10 @@ pub fn format_error(e: MalErr) -> String {
     }
 }
 
+pub fn keyword(s: &str) -> MalVal {
+    Str(format!("\u{29e}{}", s))
+}
+
 pub fn atom(mv: &MalVal) -> MalVal {
     Atom(Rc::new(RefCell::new(mv.clone())))
 }
@@ -92,7 +113,8 @@ impl MalVal {
 
     pub fn empty_q(&self) -> MalRet {
         match self {
-            List(l, _) | Vector(l, _) => Ok(Bool(l.len() == 0)),
+            List(l, _) | Vector(l, _) => Ok(Bool(l.is_empty())),
+            Set(s, _) => Ok(Bool(s.is_empty())),
             Nil => Ok(Int(0)),
             _ => error("invalid type for empty?"),
         }
@@ -101,6 +123,7 @@ impl MalVal {
     pub fn count(&self) -> MalRet {
         match self {
             List(l, _) | Vector(l, _) => Ok(Int(l.len() as i64)),
+            Set(s, _) => Ok(Int(s.len() as i64)),
             Nil => Ok(Int(0)),
             _ => error("invalid type for count"),
         }
@@ -159,7 +182,7 @@ impl MalVal {
 
     pub fn get_meta(&self) -> MalRet {
         match self {
-            List(_, meta) | Vector(_, meta) | Hash(_, meta) => Ok((**meta).clone()),
+            List(_, meta) | Vector(_, meta) | Hash(_, meta) | Set(_, meta) => Ok((**meta).clone()),
             Func(_, meta) => Ok((**meta).clone()),
             MalFunc { meta, .. } => Ok((**meta).clone()),
             _ => error("meta not supported by type"),
@@ -171,6 +194,7 @@ impl MalVal {
             List(_, ref mut meta)
             | Vector(_, ref mut meta)
             | Hash(_, ref mut meta)
+            | Set(_, ref mut meta)
             | Func(_, ref mut meta)
             | MalFunc { ref mut meta, .. } => {
                 *meta = Rc::new(new_meta.clone());
@@ -187,7 +211,10 @@ impl PartialEq for MalVal {
             (Nil, Nil) => true,
             (Bool(ref a), Bool(ref b)) => a == b,
             (Int(ref a), Int(ref b)) => a == b,
+            (BigInt(ref a), BigInt(ref b)) => a == b,
+            (Ratio(ref a), Ratio(ref b)) => a == b,
             (Float(ref a), Float(ref b)) => a == b,
+            (Char(ref a), Char(ref b)) => a == b,
             (Str(ref a), Str(ref b)) => a == b,
             (Sym(ref a), Sym(ref b)) => a == b,
             (List(ref a, _), List(ref b, _))
@@ -195,44 +222,144 @@ impl PartialEq for MalVal {
             | (List(ref a, _), Vector(ref b, _))
             | (Vector(ref a, _), List(ref b, _)) => a == b,
             (Hash(ref a, _), Hash(ref b, _)) => a == b,
-            (MalFunc { .. }, MalFunc { .. }) => false,
+            (Set(ref a, _), Set(ref b, _)) => a == b,
+            // Functions and atoms are equal only to themselves.
+            (Func(a, _), Func(b, _)) => std::ptr::fn_addr_eq(*a, *b),
+            (MalFunc { ast: a, env: ea, .. }, MalFunc { ast: b, env: eb, .. }) => {
+                Rc::ptr_eq(a, b) && Rc::ptr_eq(ea, eb)
+            }
+            (Atom(a), Atom(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 }
 
+// NaN is the one value not equal to itself, so it cannot be found as a key.
+impl Eq for MalVal {}
+
+// Sums the items' own hashes so that the result does not depend on order.
+fn hash_unordered<T: hash::Hash, H: Hasher>(items: impl ExactSizeIterator<Item = T>, state: &mut H) {
+    items.len().hash(state);
+    let sum = items.fold(0u64, |sum, item| {
+        let mut h = FnvHasher::default();
+        item.hash(&mut h);
+        sum.wrapping_add(h.finish())
+    });
+    sum.hash(state);
+}
+
+// Agrees with PartialEq: lists and vectors with equal elements hash alike,
+// maps and sets ignore order, and 0.0 hashes like -0.0.
+impl hash::Hash for MalVal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Nil => 0u8.hash(state),
+            Bool(b) => (1u8, b).hash(state),
+            Int(n) => (2u8, n).hash(state),
+            BigInt(n) => (3u8, n).hash(state),
+            Ratio(r) => (4u8, r).hash(state),
+            Float(f) => (5u8, if *f == 0.0 { 0 } else { f.to_bits() }).hash(state),
+            Char(c) => (6u8, c).hash(state),
+            Str(s) => (7u8, s).hash(state),
+            Sym(s) => (8u8, s).hash(state),
+            List(v, _) | Vector(v, _) => (9u8, v).hash(state),
+            Hash(hm, _) => {
+                10u8.hash(state);
+                hash_unordered(hm.iter(), state);
+            }
+            Set(v, _) => {
+                11u8.hash(state);
+                hash_unordered(v.iter(), state);
+            }
+            Func(f, _) => (12u8, *f as usize).hash(state),
+            MalFunc { ast, .. } => (13u8, Rc::as_ptr(ast)).hash(state),
+            Atom(a) => (14u8, Rc::as_ptr(a)).hash(state),
+        }
+    }
+}
+
 pub fn func(f: fn(MalArgs) -> MalRet) -> MalVal {
     Func(f, Rc::new(Nil))
 }
 
-pub fn _assoc(mut hm: FnvHashMap<String, MalVal>, kvs: MalArgs) -> MalRet {
-    if kvs.len() % 2 != 0 {
+pub fn _assoc(mut hm: MalMap, kvs: MalArgs) -> MalRet {
+    if !kvs.len().is_multiple_of(2) {
         return error("odd number of elements");
     }
-    for (k, v) in kvs.iter().tuples() {
-        match k {
-            Str(s) => {
-                hm.insert(s.to_string(), v.clone());
-            }
-            _ => return error("key is not string"),
-        }
+    for (k, v) in kvs.into_iter().tuples() {
+        hm.insert(k, v);
     }
     Ok(Hash(Rc::new(hm), Rc::new(Nil)))
 }
 
-pub fn _dissoc(mut hm: FnvHashMap<String, MalVal>, ks: MalArgs) -> MalRet {
+pub fn _dissoc(mut hm: MalMap, ks: MalArgs) -> MalRet {
     for k in ks {
-        match k {
-            Str(ref s) => {
-                let _ = hm.remove(s);
-            }
-            _ => return error("key is not string"),
-        }
+        hm.shift_remove(&k);
     }
     Ok(Hash(Rc::new(hm), Rc::new(Nil)))
 }
 
+// Duplicates after the first are dropped.
+pub fn hash_set(items: MalArgs) -> MalVal {
+    Set(Rc::new(items.into_iter().collect()), Rc::new(Nil))
+}
+
 pub fn hash_map(kvs: MalArgs) -> MalRet {
-    let hm: FnvHashMap<String, MalVal> = FnvHashMap::default();
+    let hm = MalMap::default();
     _assoc(hm, kvs)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::keyword as kw;
+
+    #[test]
+    fn test_hash_map_order() {
+        let m = hash_map(vec![kw("z"), Int(1), kw("a"), Int(2), kw("m"), Int(3)]).ok().unwrap();
+        assert_eq!(m.pr_str(true), "{:z 1 :a 2 :m 3}");
+        let Hash(hm, _) = &m else { panic!() };
+        let updated = _assoc((**hm).clone(), vec![kw("b"), Int(4), kw("z"), Int(9)]).ok().unwrap();
+        assert_eq!(updated.pr_str(true), "{:z 9 :a 2 :m 3 :b 4}");
+        let removed = _dissoc((**hm).clone(), vec![kw("a")]).ok().unwrap();
+        assert_eq!(removed.pr_str(true), "{:z 1 :m 3}");
+    }
+
+    #[test]
+    fn test_any_key_type() {
+        let k = vector![Int(1), kw("a")];
+        let m = hash_map(vec![Int(1), Str("one".to_string()), k, Nil, Nil, Int(0)]).ok().unwrap();
+        assert_eq!(m.pr_str(true), "{1 \"one\" [1 :a] nil nil 0}");
+        let Hash(hm, _) = &m else { panic!() };
+        assert!(matches!(hm.get(&list![Int(1), kw("a")]), Some(Nil)));
+        assert!(matches!(hm.get(&Int(1)), Some(Str(_))));
+        assert!(hm.get(&Float(1.0)).is_none());
+    }
+
+    #[test]
+    fn test_hash_agrees_with_eq() {
+        use std::hash::BuildHasher;
+        let h = |v: &MalVal| FnvBuildHasher::default().hash_one(v);
+        let a = hash_map(vec![kw("x"), Int(1), kw("y"), Int(2)]).ok().unwrap();
+        let b = hash_map(vec![kw("y"), Int(2), kw("x"), Int(1)]).ok().unwrap();
+        assert_eq!(h(&a), h(&b));
+        assert_eq!(h(&list![Int(1), Int(2)]), h(&vector![Int(1), Int(2)]));
+        assert_eq!(h(&Float(0.0)), h(&Float(-0.0)));
+        assert_eq!(h(&hash_set(vec![Int(1), Int(2)])), h(&hash_set(vec![Int(2), Int(1)])));
+    }
+
+    #[test]
+    fn test_hash_set() {
+        let s = hash_set(vec![Int(3), Int(1), Int(3), Float(1.0), Int(1)]);
+        assert_eq!(s.pr_str(true), "#{3 1 1.0}");
+        assert!(s == hash_set(vec![Float(1.0), Int(1), Int(3)]));
+        assert!(s != hash_set(vec![Int(1), Int(3)]));
+    }
+
+    #[test]
+    fn test_hash_map_equality_ignores_order() {
+        let a = hash_map(vec![kw("x"), Int(1), kw("y"), Int(2)]).ok().unwrap();
+        let b = hash_map(vec![kw("y"), Int(2), kw("x"), Int(1)]).ok().unwrap();
+        assert!(a == b);
+    }
+}